@@ -0,0 +1,159 @@
+use crate::api::pdf::with_pdf_source;
+use crate::api::usage::{record_usage, UsageContext};
+use crate::api::AppState;
+use crate::db::DocumentArtifact;
+use crate::error::ApiError;
+use axum::{
+    extract::{Path, Query, State},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// A built-in analysis that can be run on a document with one click
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DocumentAction {
+    Tldr,
+    SectionSummary,
+    Limitations,
+    MethodologyCritique,
+    FutureWork,
+}
+
+impl DocumentAction {
+    pub fn parse(action: &str) -> Option<Self> {
+        match action {
+            "tldr" => Some(Self::Tldr),
+            "section_summary" => Some(Self::SectionSummary),
+            "limitations" => Some(Self::Limitations),
+            "methodology_critique" => Some(Self::MethodologyCritique),
+            "future_work" => Some(Self::FutureWork),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Tldr => "tldr",
+            Self::SectionSummary => "section_summary",
+            Self::Limitations => "limitations",
+            Self::MethodologyCritique => "methodology_critique",
+            Self::FutureWork => "future_work",
+        }
+    }
+
+    fn instructions(self) -> &'static str {
+        match self {
+            Self::Tldr => "Write a TL;DR of this document: 3-5 sentences covering the problem, the approach and the main result. Reference pages as (page X).",
+            Self::SectionSummary => "Summarize this document section by section. Use one markdown heading per section, in document order, followed by a short paragraph on what the section says and the page it starts on as (page X).",
+            Self::Limitations => "List the limitations of this document as markdown bullet points: both the ones the authors acknowledge and any they do not. Mark which is which and reference pages as (page X).",
+            Self::MethodologyCritique => "Critique the methodology of this document. Assess the study design, data, baselines, evaluation and statistical analysis, and whether the conclusions follow from the results. Reference pages as (page X).",
+            Self::FutureWork => "List promising directions for future work based on this document, as markdown bullet points: those the authors suggest and open questions the results raise. Reference pages as (page X).",
+        }
+    }
+
+    fn max_tokens(self) -> u32 {
+        match self {
+            Self::Tldr => 512,
+            _ => 2048,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RunActionQuery {
+    /// Run the action again even if a stored result exists
+    #[serde(default)]
+    pub refresh: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListArtifactsQuery {
+    pub action: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ActionResponse {
+    #[serde(flatten)]
+    pub artifact: DocumentArtifact,
+    /// Whether the stored result was returned instead of running the action
+    pub cached: bool,
+}
+
+/// Run a built-in analysis on a document, returning the latest stored result unless
+/// `refresh` is set
+pub async fn run_action_handler(
+    State(state): State<Arc<AppState>>,
+    Path((document_id, action)): Path<(String, String)>,
+    Query(query): Query<RunActionQuery>,
+) -> Result<Json<ActionResponse>, ApiError> {
+    let action = parse_action(&action)?;
+
+    if !query.refresh {
+        let latest = state
+            .chat_db
+            .latest_artifact(&document_id, action.as_str())
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+        if let Some(artifact) = latest {
+            return Ok(Json(ActionResponse {
+                artifact,
+                cached: true,
+            }));
+        }
+    }
+
+    let (content, usage) = with_pdf_source(&state, &document_id, |source| {
+        state
+            .claude
+            .analyze_document(source, action.instructions(), action.max_tokens())
+    })
+    .await?;
+
+    let context = UsageContext {
+        kind: "action",
+        document_id: Some(&document_id),
+        ..Default::default()
+    };
+    record_usage(&state, context, state.claude.model(), &usage).await;
+
+    // Only once the PDF was read, so an unknown id never gets a document row
+    state
+        .chat_db
+        .ensure_document(&document_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    let artifact = state
+        .chat_db
+        .save_artifact(&document_id, action.as_str(), &content, state.claude.model())
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    Ok(Json(ActionResponse {
+        artifact,
+        cached: false,
+    }))
+}
+
+/// List every stored artifact version for a document, newest first
+pub async fn list_artifacts_handler(
+    State(state): State<Arc<AppState>>,
+    Path(document_id): Path<String>,
+    Query(query): Query<ListArtifactsQuery>,
+) -> Result<Json<Vec<DocumentArtifact>>, ApiError> {
+    let action = query.action.as_deref().map(parse_action).transpose()?;
+
+    let artifacts = state
+        .chat_db
+        .list_artifacts(&document_id, action.map(DocumentAction::as_str))
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    Ok(Json(artifacts))
+}
+
+fn parse_action(action: &str) -> Result<DocumentAction, ApiError> {
+    DocumentAction::parse(action)
+        .ok_or_else(|| ApiError::NotFound(format!("Unknown document action: {}", action)))
+}
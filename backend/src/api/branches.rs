@@ -0,0 +1,281 @@
+use crate::api::chat::{
+    begin_exchange, build_chat_request, collect_reply, complete_exchange, fail_exchange,
+    to_transcript, validate_selection, UserTurn,
+};
+use crate::api::conversations::find_conversation;
+use crate::api::history::compact_history;
+use crate::api::tools::run_with_tools;
+use crate::api::turns::ReplyGuard;
+use crate::api::usage::{record_usage, UsageContext};
+use crate::api::AppState;
+use crate::db::{Conversation, StoredMessage};
+use crate::error::ApiError;
+use crate::models::{ChatApiResponse, GenerationOptions, MessageStatus, Selection};
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+#[derive(Debug, Deserialize)]
+pub struct EditMessageRequest {
+    pub content: String,
+    /// Passage the edited message asks about; defaults to the original's
+    #[serde(default)]
+    pub selection: Option<Selection>,
+    #[serde(flatten)]
+    pub generation: GenerationOptions,
+}
+
+/// The alternative versions of a message, i.e. all messages sharing its parent
+#[derive(Debug, Serialize)]
+pub struct BranchList {
+    pub parent_message_id: Option<String>,
+    /// The sibling on the conversation's active path, if any
+    pub active_message_id: Option<String>,
+    pub messages: Vec<StoredMessage>,
+}
+
+/// Generate a new reply to the user message an assistant message answered,
+/// as a sibling of that message
+pub async fn regenerate_message_handler(
+    State(state): State<Arc<AppState>>,
+    Path((document_id, conversation_id, message_id)): Path<(String, String, String)>,
+    options: Option<Json<GenerationOptions>>,
+) -> Result<Json<ChatApiResponse>, ApiError> {
+    let conversation = find_conversation(&state, &document_id, &conversation_id).await?;
+    let message = find_message(&state, &conversation_id, &message_id).await?;
+    if message.role != "assistant" {
+        return Err(ApiError::BadRequest(
+            "Only assistant messages can be regenerated".to_string(),
+        ));
+    }
+    let parent_message_id = message.parent_message_id.ok_or_else(|| {
+        ApiError::BadRequest("Message does not answer a user message".to_string())
+    })?;
+
+    let options = options.map(|Json(options)| options).unwrap_or_default();
+    let message_id = begin_exchange(&state, &conversation_id, Some(&parent_message_id), None).await?;
+    let reply = ReplyGuard::new(&state, &message_id);
+    let response = generate_reply(&state, &conversation, reply, &options).await?;
+
+    Ok(Json(response))
+}
+
+/// Replace a user message with an edited copy, as a sibling of the original, and
+/// answer it
+pub async fn edit_message_handler(
+    State(state): State<Arc<AppState>>,
+    Path((document_id, conversation_id, message_id)): Path<(String, String, String)>,
+    Json(payload): Json<EditMessageRequest>,
+) -> Result<Json<ChatApiResponse>, ApiError> {
+    let conversation = find_conversation(&state, &document_id, &conversation_id).await?;
+    let message = find_message(&state, &conversation_id, &message_id).await?;
+    if message.role != "user" {
+        return Err(ApiError::BadRequest("Only user messages can be edited".to_string()));
+    }
+    if payload.content.trim().is_empty() {
+        return Err(ApiError::BadRequest("Message must not be empty".to_string()));
+    }
+    if let Some(selection) = &payload.selection {
+        validate_selection(selection)?;
+    }
+    let user_message = UserTurn {
+        content: payload.content,
+        selection: payload
+            .selection
+            .or_else(|| message.selection.map(|selection| selection.0)),
+    };
+
+    let message_id = begin_exchange(
+        &state,
+        &conversation_id,
+        message.parent_message_id.as_deref(),
+        Some(&user_message),
+    )
+    .await?;
+    let reply = ReplyGuard::new(&state, &message_id);
+    let response = generate_reply(&state, &conversation, reply, &payload.generation).await?;
+
+    Ok(Json(response))
+}
+
+/// Generate a failed reply again, filling in the same message, and make it the
+/// conversation's active branch
+pub async fn retry_message_handler(
+    State(state): State<Arc<AppState>>,
+    Path((document_id, conversation_id, message_id)): Path<(String, String, String)>,
+    options: Option<Json<GenerationOptions>>,
+) -> Result<Json<ChatApiResponse>, ApiError> {
+    let conversation = find_conversation(&state, &document_id, &conversation_id).await?;
+    let message = find_message(&state, &conversation_id, &message_id).await?;
+    if message.role != "assistant" || message.status != MessageStatus::Failed {
+        return Err(ApiError::BadRequest(
+            "Only failed assistant messages can be retried".to_string(),
+        ));
+    }
+
+    let restarted = state
+        .chat_db
+        .restart_message(&message_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    if !restarted {
+        return Err(ApiError::Conflict("Message is already being retried".to_string()));
+    }
+    let reply = ReplyGuard::new(&state, &message_id);
+
+    state
+        .chat_db
+        .set_active_leaf(&conversation_id, &message_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    let options = options.map(|Json(options)| options).unwrap_or_default();
+    let response = generate_reply(&state, &conversation, reply, &options).await?;
+
+    Ok(Json(response))
+}
+
+/// List a message and its siblings, marking the one on the active path
+pub async fn list_branches_handler(
+    State(state): State<Arc<AppState>>,
+    Path((document_id, conversation_id, message_id)): Path<(String, String, String)>,
+) -> Result<Json<BranchList>, ApiError> {
+    let conversation = find_conversation(&state, &document_id, &conversation_id).await?;
+    let message = find_message(&state, &conversation_id, &message_id).await?;
+
+    let messages = state
+        .chat_db
+        .list_sibling_messages(&conversation_id, message.parent_message_id.as_deref())
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    let active_path = match &conversation.active_leaf_id {
+        Some(leaf) => state
+            .chat_db
+            .get_message_path(leaf)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?,
+        None => Vec::new(),
+    };
+    let active_message_id = messages
+        .iter()
+        .find(|m| active_path.iter().any(|p| p.id == m.id))
+        .map(|m| m.id.clone());
+
+    Ok(Json(BranchList {
+        parent_message_id: message.parent_message_id,
+        active_message_id,
+        messages,
+    }))
+}
+
+/// Switch the conversation to the branch containing a message, following its most
+/// recent replies, and return the new active path
+pub async fn activate_branch_handler(
+    State(state): State<Arc<AppState>>,
+    Path((document_id, conversation_id, message_id)): Path<(String, String, String)>,
+) -> Result<Json<Vec<StoredMessage>>, ApiError> {
+    find_conversation(&state, &document_id, &conversation_id).await?;
+    find_message(&state, &conversation_id, &message_id).await?;
+
+    let leaf = state
+        .chat_db
+        .latest_leaf(&message_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    state
+        .chat_db
+        .set_active_leaf(&conversation_id, &leaf)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    let messages = state
+        .chat_db
+        .get_conversation_messages_by_id(&conversation_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    Ok(Json(messages))
+}
+
+async fn find_message(
+    state: &AppState,
+    conversation_id: &str,
+    message_id: &str,
+) -> Result<StoredMessage, ApiError> {
+    state
+        .chat_db
+        .get_message(conversation_id, message_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?
+        .ok_or_else(|| ApiError::NotFound(format!("Message not found: {}", message_id)))
+}
+
+/// Ask Claude to answer the history leading up to the pending assistant message and
+/// fill it in, or mark it failed if Claude cannot answer or the client disconnects
+async fn generate_reply(
+    state: &Arc<AppState>,
+    conversation: &Conversation,
+    reply: ReplyGuard,
+    options: &GenerationOptions,
+) -> Result<ChatApiResponse, ApiError> {
+    let result = answer_pending(state, conversation, reply.message_id(), options).await;
+    if let Err(e) = &result {
+        fail_exchange(state, reply.message_id(), &e.to_string()).await;
+    }
+    reply.finish();
+    result
+}
+
+async fn answer_pending(
+    state: &Arc<AppState>,
+    conversation: &Conversation,
+    message_id: &str,
+    options: &GenerationOptions,
+) -> Result<ChatApiResponse, ApiError> {
+    let params = state.model_policy.resolve(options)?;
+
+    let history = state
+        .chat_db
+        .get_message_path(message_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    let compacted = compact_history(state, conversation, history).await?;
+    let transcript = to_transcript(compacted.messages);
+
+    let (mut document_ids, request) =
+        build_chat_request(state, conversation, &[], compacted.summary, transcript, params).await?;
+    let model = request.model.clone();
+
+    let outcome = run_with_tools(state, &conversation.id, request, &mut document_ids).await?;
+
+    let reply = collect_reply(
+        outcome.content,
+        &document_ids,
+        model,
+        outcome.tool_calls,
+        options.include_thinking,
+        outcome.stop_reason,
+    );
+
+    complete_exchange(state, &conversation.id, message_id, &reply).await?;
+
+    let context = UsageContext {
+        kind: "chat",
+        conversation_id: Some(&conversation.id),
+        document_id: Some(&conversation.document_id),
+        message_id: Some(message_id),
+    };
+    record_usage(state, context, &reply.model, &outcome.usage).await;
+
+    Ok(reply.into_response(
+        message_id.to_string(),
+        conversation.id.clone(),
+        document_ids,
+        outcome.usage,
+    ))
+}
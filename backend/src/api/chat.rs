@@ -1,129 +1,893 @@
-use crate::claude::{ChatRequest, ClaudeClient, ResponseContent, SystemBlock};
-use crate::db::{ChatDatabase, StoredMessage};
+use crate::api::conversations::{find_conversation, spawn_title_generation};
+use crate::api::history::compact_history;
+use crate::api::pdf::{load_pdf_source, replace_missing_files};
+use crate::api::tools::run_with_tools;
+use crate::api::turns::{begin_turn, TurnGuard, TurnStart};
+use crate::api::usage::{record_usage, UsageContext};
+use crate::claude::{
+    ChatRequest, ClaudeClient, ContentDelta, ResponseContent, StopReason, StreamEvent,
+    SystemBlock, ThinkingConfig, Usage,
+};
+use crate::config::{FilesApiPolicy, GenerationParams, HistoryPolicy, ModelPolicy, PriceTable};
+use crate::db::{ChatDatabase, CompletedMessage, Conversation, NewMessage, StoredMessage};
 use crate::error::ApiError;
-use crate::models::{ChatApiRequest, ChatApiResponse};
+use crate::models::{
+    parse_page_citations, ChatApiRequest, ChatApiResponse, Citation, DocumentQuote,
+    MessageStatus, Selection, ToolCall,
+};
 use crate::storage::FileStorage;
-use axum::{extract::{Path, State}, Json};
+use axum::{
+    extract::{Path, State},
+    http::HeaderMap,
+    response::sse::{Event, KeepAlive, Sse},
+    Json,
+};
+use futures_util::{Stream, StreamExt};
 use moka::future::Cache;
+use serde::Serialize;
+use std::convert::Infallible;
 use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
 
 pub struct AppState {
     pub claude: ClaudeClient,
+    pub model_policy: ModelPolicy,
+    pub pricing: PriceTable,
+    pub history_policy: HistoryPolicy,
     pub storage: Arc<dyn FileStorage>,
     pub pdf_cache: Cache<String, String>, // document_id -> base64
+    pub files_policy: FilesApiPolicy,
+    pub file_ids: Cache<String, String>, // document_id -> checked Files API id
     pub chat_db: ChatDatabase,
 }
 
-const SYSTEM_PROMPT: &str = r#"You are an AI assistant helping users understand research papers.
+/// Role used when a conversation has no prompt preset
+const DEFAULT_ROLE_PROMPT: &str = "You are an AI assistant helping users understand research papers.";
 
-Guidelines:
+/// Rules appended to every role, default or preset, so answers stay grounded and
+/// page references stay parseable
+const GUIDELINES_PROMPT: &str = r#"Guidelines:
 - CRITICAL: Always format page references using EXACTLY this format: (page X) for single pages or (page X, page Y) for multiple pages
 - ONLY state information you can actually find in the PDF content
 - NEVER make assumptions or educated guesses
 - If you cannot find specific information, clearly state "I cannot find this information in the paper"
+- When a question comes with a <selected_passage>, it is about that passage on the given page
 - Use markdown formatting for better readability
 - Be concise and clear in your explanations"#;
 
+const MULTI_DOCUMENT_PROMPT: &str = r#"Multiple documents are attached, each titled with its filename.
+- When referring to a page, ALWAYS name the document too, using EXACTLY this format: (filename, page X)
+- Make clear which document each statement comes from, especially when comparing them"#;
+
+const MAX_SELECTION_CHARS: usize = 10_000;
+
 pub async fn chat_handler(
     State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
     Json(payload): Json<ChatApiRequest>,
 ) -> Result<Json<ChatApiResponse>, ApiError> {
-    // Get or create conversation for this document
-    let conversation_id = state
-        .chat_db
-        .get_or_create_conversation(&payload.document_id)
-        .await
-        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    let mut turn = match begin_turn(&state, &headers, &payload).await? {
+        TurnStart::Replay(response) => return Ok(Json(response)),
+        TurnStart::Started(turn) => turn,
+    };
 
-    // Get PDF from cache or storage
-    let pdf_base64 = match state.pdf_cache.get(&payload.document_id).await {
-        Some(cached) => cached,
-        None => {
-            // Not in cache, fetch from storage and encode
-            let base64 = state
-                .storage
-                .get_pdf_base64(&payload.document_id)
+    match answer(&state, &payload, &mut turn).await {
+        Ok(response) => {
+            turn.complete(&response).await;
+            Ok(Json(response))
+        }
+        Err(e) => {
+            turn.fail(&e.to_string()).await;
+            Err(e)
+        }
+    }
+}
+
+async fn answer(
+    state: &Arc<AppState>,
+    payload: &ChatApiRequest,
+    turn: &mut TurnGuard,
+) -> Result<ChatApiResponse, ApiError> {
+    let PreparedChat {
+        conversation_id,
+        message_id,
+        mut document_ids,
+        request,
+    } = prepare_chat_request(state, payload, turn).await?;
+    let model = request.model.clone();
+
+    let outcome = run_with_tools(state, &conversation_id, request, &mut document_ids).await?;
+
+    let reply = collect_reply(
+        outcome.content,
+        &document_ids,
+        model,
+        outcome.tool_calls,
+        payload.generation.include_thinking,
+        outcome.stop_reason,
+    );
+
+    complete_exchange(state, &conversation_id, &message_id, &reply).await?;
+
+    let context = UsageContext {
+        kind: "chat",
+        conversation_id: Some(&conversation_id),
+        document_id: Some(&payload.document_id),
+        message_id: Some(&message_id),
+    };
+    record_usage(state, context, &reply.model, &outcome.usage).await;
+
+    Ok(reply.into_response(
+        message_id,
+        conversation_id,
+        document_ids,
+        outcome.usage,
+    ))
+}
+
+/// Stream the assistant reply as server-sent events.
+///
+/// Emits a `conversation` event naming the conversation, then `delta` events carrying
+/// text chunks, preceded by `thinking` events carrying reasoning chunks when
+/// `include_thinking` is set. Once the reply is complete it ends with `citations`,
+/// `quotes` and `stop` events and a single `usage` event, or an `error` event if the
+/// upstream stream fails. A reply cut off by `max_tokens` is not continued; the `stop`
+/// event marks it as truncated.
+///
+/// The user turn and a pending reply, named in the `conversation` event, are stored up
+/// front; the reply is filled in once it has been received in full, or marked failed.
+/// If the client disconnects first, the upstream stream is dropped and the turn recorded
+/// as cancelled. A repeated `Idempotency-Key` replays the stored reply as a single
+/// `delta`. Tools are only offered on the non-streaming endpoint, as API.md documents.
+pub async fn chat_stream_handler(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(payload): Json<ChatApiRequest>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApiError> {
+    let (tx, rx) = mpsc::channel::<Event>(32);
+    let stream = ReceiverStream::new(rx).map(Ok);
+
+    let mut turn = match begin_turn(&state, &headers, &payload).await? {
+        TurnStart::Replay(response) => {
+            for event in replay_events(&response) {
+                let _ = tx.send(event).await;
+            }
+            return Ok(Sse::new(stream).keep_alive(KeepAlive::default()));
+        }
+        TurnStart::Started(turn) => turn,
+    };
+
+    let prepared = match prepare_chat_request(&state, &payload, &mut turn).await {
+        Ok(prepared) => prepared,
+        Err(e) => {
+            turn.fail(&e.to_string()).await;
+            return Err(e);
+        }
+    };
+    let PreparedChat {
+        conversation_id,
+        message_id,
+        document_ids,
+        mut request,
+    } = prepared;
+    let model = request.model.clone();
+    let include_thinking = payload.generation.include_thinking;
+
+    let mut upstream = state.claude.chat_stream(&request).await;
+    if let Err(e) = &upstream {
+        // Claude may no longer have a PDF uploaded through the Files API
+        match replace_missing_files(&state, &mut request, e).await {
+            Ok(true) => upstream = state.claude.chat_stream(&request).await,
+            Ok(false) => {}
+            Err(e) => {
+                turn.fail(&e.to_string()).await;
+                return Err(e);
+            }
+        }
+    }
+    let upstream = match upstream {
+        Ok(upstream) => upstream,
+        Err(e) => {
+            let e = ApiError::UpstreamError(format!("Claude API error: {}", e));
+            turn.fail(&e.to_string()).await;
+            return Err(e);
+        }
+    };
+
+    let _ = tx
+        .send(json_event(
+            "conversation",
+            &serde_json::json!({
+                "conversation_id": conversation_id,
+                "message_id": message_id,
+                "document_ids": document_ids,
+            }),
+        ))
+        .await;
+
+    // The upstream stream is driven in a separate task that stops reading, dropping
+    // the connection to Claude, as soon as the browser goes away
+    tokio::spawn(async move {
+        let mut upstream = Box::pin(upstream);
+        let mut text = String::new();
+        let mut text_chars = 0;
+        let mut thinking = String::new();
+        let mut usage: Option<Usage> = None;
+        let mut stop_reason = None;
+
+        // Citations arrive within a text block and cover the whole block
+        let mut quotes = Vec::new();
+        let mut block_start = 0;
+        let mut block_citations = Vec::new();
+
+        let end = loop {
+            let event = tokio::select! {
+                _ = tx.closed() => break StreamEnd::Cancelled,
+                event = upstream.next() => match event {
+                    Some(event) => event,
+                    None => break StreamEnd::Failed("Claude API stream ended unexpectedly".to_string()),
+                },
+            };
+
+            match event {
+                Ok(StreamEvent::MessageStart { message }) => usage = Some(message.usage),
+                Ok(StreamEvent::ContentBlockStart) => block_start = text_chars,
+                Ok(StreamEvent::ContentBlockDelta {
+                    delta: ContentDelta::TextDelta { text: chunk },
+                }) => {
+                    text.push_str(&chunk);
+                    text_chars += chunk.chars().count();
+                    if tx
+                        .send(json_event("delta", &serde_json::json!({ "text": chunk })))
+                        .await
+                        .is_err()
+                    {
+                        break StreamEnd::Cancelled;
+                    }
+                }
+                Ok(StreamEvent::ContentBlockDelta {
+                    delta: ContentDelta::ThinkingDelta { thinking: chunk },
+                }) if include_thinking => {
+                    thinking.push_str(&chunk);
+                    if tx
+                        .send(json_event("thinking", &serde_json::json!({ "text": chunk })))
+                        .await
+                        .is_err()
+                    {
+                        break StreamEnd::Cancelled;
+                    }
+                }
+                Ok(StreamEvent::ContentBlockDelta {
+                    delta: ContentDelta::CitationsDelta { citation },
+                }) => block_citations.push(citation),
+                Ok(StreamEvent::ContentBlockStop) => {
+                    quotes.extend(block_citations.drain(..).map(|c| {
+                        DocumentQuote::from_citation(c, &document_ids, block_start, text_chars)
+                    }));
+                }
+                Ok(StreamEvent::MessageDelta {
+                    delta,
+                    usage: delta_usage,
+                }) => {
+                    stop_reason = delta.stop_reason.or(stop_reason);
+                    if let Some(usage) = usage.as_mut() {
+                        usage.output_tokens = delta_usage.output_tokens;
+                    }
+                }
+                Ok(StreamEvent::MessageStop) => break StreamEnd::Completed,
+                Ok(StreamEvent::Error { error }) => {
+                    break StreamEnd::Failed(format!(
+                        "Claude API error: {}: {}",
+                        error.error_type, error.message
+                    ))
+                }
+                Ok(_) => {}
+                Err(e) => break StreamEnd::Failed(format!("Claude API error: {}", e)),
+            }
+        };
+
+        // Claude bills the input from `message_start` on, however the stream ends
+        if let Some(usage) = &usage {
+            let context = UsageContext {
+                kind: "chat",
+                conversation_id: Some(&conversation_id),
+                document_id: Some(&payload.document_id),
+                message_id: Some(&message_id),
+            };
+            record_usage(&state, context, &model, usage).await;
+        }
+
+        match end {
+            StreamEnd::Completed => {}
+            // Dropping `turn` here records the turn as cancelled
+            StreamEnd::Cancelled => return,
+            StreamEnd::Failed(message) => {
+                let _ = tx.send(error_event(&message)).await;
+                turn.fail(&message).await;
+                return;
+            }
+        }
+
+        let reply = AssistantReply {
+            citations: parse_page_citations(&text),
+            text,
+            quotes,
+            model,
+            tool_calls: Vec::new(),
+            thinking: include_thinking.then_some(thinking),
+            stop_reason,
+        };
+
+        if let Err(e) = complete_exchange(&state, &conversation_id, &message_id, &reply).await {
+            let _ = tx.send(error_event(&e.to_string())).await;
+            turn.fail(&e.to_string()).await;
+            return;
+        }
+
+        let _ = tx
+            .send(json_event("message", &serde_json::json!({ "message_id": message_id })))
+            .await;
+        let _ = tx.send(json_event("citations", &reply.citations)).await;
+        let _ = tx.send(json_event("quotes", &reply.quotes)).await;
+        let _ = tx.send(stop_event(reply.stop_reason)).await;
+
+        if let Some(usage) = &usage {
+            let _ = tx.send(json_event("usage", usage)).await;
+        }
+
+        let response = reply.into_response(
+            message_id,
+            conversation_id,
+            document_ids,
+            usage.unwrap_or_default(),
+        );
+        turn.complete(&response).await;
+    });
+
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+/// How reading a Claude stream ended
+enum StreamEnd {
+    Completed,
+    /// The browser went away
+    Cancelled,
+    Failed(String),
+}
+
+/// The events a stream for `response` would have sent, for replaying a completed turn
+fn replay_events(response: &ChatApiResponse) -> Vec<Event> {
+    let mut events = vec![json_event(
+        "conversation",
+        &serde_json::json!({
+            "conversation_id": response.conversation_id,
+            "message_id": response.message_id,
+            "document_ids": response.document_ids,
+        }),
+    )];
+    if let Some(thinking) = &response.thinking {
+        events.push(json_event("thinking", &serde_json::json!({ "text": thinking })));
+    }
+    events.extend([
+        json_event("delta", &serde_json::json!({ "text": response.response })),
+        json_event("message", &serde_json::json!({ "message_id": response.message_id })),
+        json_event("citations", &response.citations),
+        json_event("quotes", &response.quotes),
+        stop_event(response.stop_reason),
+    ]);
+    if let Some(usage) = &response.usage {
+        events.push(json_event("usage", usage));
+    }
+    events
+}
+
+/// A chat request ready to send, along with the pending reply it will fill in
+struct PreparedChat {
+    conversation_id: String,
+    /// The pending assistant message
+    message_id: String,
+    /// Every document attached to the prompt, primary document first
+    document_ids: Vec<String>,
+    request: ChatRequest,
+}
+
+/// A new user message, along with the passage it asks about
+pub struct UserTurn {
+    pub content: String,
+    pub selection: Option<Selection>,
+}
+
+impl UserTurn {
+    /// The message as sent to Claude
+    pub fn prompt(&self) -> String {
+        match &self.selection {
+            Some(selection) => selection.annotate(&self.content),
+            None => self.content.clone(),
+        }
+    }
+}
+
+/// A complete assistant answer, ready to be stored and returned
+pub struct AssistantReply {
+    pub text: String,
+    pub citations: Vec<Citation>,
+    pub quotes: Vec<DocumentQuote>,
+    pub model: String,
+    pub tool_calls: Vec<ToolCall>,
+    /// Claude's reasoning, kept only when the client asked for it
+    pub thinking: Option<String>,
+    pub stop_reason: Option<StopReason>,
+}
+
+impl AssistantReply {
+    pub fn into_response(
+        self,
+        message_id: String,
+        conversation_id: String,
+        document_ids: Vec<String>,
+        usage: Usage,
+    ) -> ChatApiResponse {
+        ChatApiResponse {
+            message_id,
+            response: self.text,
+            conversation_id,
+            document_ids,
+            citations: self.citations,
+            quotes: self.quotes,
+            model: self.model,
+            tool_calls: self.tool_calls,
+            thinking: self.thinking,
+            truncated: self.stop_reason == Some(StopReason::MaxTokens),
+            stop_reason: self.stop_reason,
+            usage: Some(usage),
+        }
+    }
+}
+
+/// Resolve the conversation for the payload, store the user turn and a pending reply,
+/// and build the Claude request. A retried turn reuses the reply of its earlier attempt
+/// instead of storing the user turn again
+async fn prepare_chat_request(
+    state: &Arc<AppState>,
+    payload: &ChatApiRequest,
+    turn: &mut TurnGuard,
+) -> Result<PreparedChat, ApiError> {
+    let params = state.model_policy.resolve(&payload.generation)?;
+
+    let has_transcript = !payload.messages.is_empty();
+    match (payload.message.is_some(), has_transcript) {
+        (false, false) => {
+            return Err(ApiError::BadRequest(
+                "Provide either `message` or `messages`".to_string(),
+            ))
+        }
+        (true, true) => {
+            return Err(ApiError::BadRequest(
+                "Provide `message` or `messages`, not both".to_string(),
+            ))
+        }
+        _ => {}
+    }
+    if let Some(selection) = &payload.selection {
+        validate_selection(selection)?;
+    }
+
+    // The new user turn is either `message` or the end of the client transcript
+    let user_message = match &payload.message {
+        Some(message) => Some(message.clone()),
+        None => payload
+            .messages
+            .last()
+            .filter(|m| m.role == "user")
+            .map(|m| m.content.clone()),
+    }
+    .map(|content| UserTurn {
+        content,
+        selection: payload.selection.clone(),
+    });
+    if payload.selection.is_some() && user_message.is_none() {
+        return Err(ApiError::BadRequest(
+            "A selection must come with a user message".to_string(),
+        ));
+    }
+
+    let (conversation, message_id) = match turn.resumed_reply() {
+        // A retried turn fills in the reply its earlier attempt left failed, below the
+        // user message that attempt stored
+        Some((conversation_id, message_id)) => {
+            let conversation = find_conversation(state, &payload.document_id, conversation_id).await?;
+            state
+                .chat_db
+                .set_active_leaf(conversation_id, message_id)
                 .await
-                .map_err(|e| ApiError::NotFound(format!("Document not found: {}", e)))?;
+                .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+            (conversation, message_id.to_string())
+        }
+        None => {
+            // Use the requested conversation, or get or create one for this document
+            let conversation_id = match &payload.conversation_id {
+                Some(conversation_id) => {
+                    find_conversation(state, &payload.document_id, conversation_id).await?;
+                    state
+                        .chat_db
+                        .touch_conversation(conversation_id)
+                        .await
+                        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+                    conversation_id.clone()
+                }
+                None => state
+                    .chat_db
+                    .get_or_create_conversation(&payload.document_id)
+                    .await
+                    .map_err(|e| ApiError::DatabaseError(e.to_string()))?,
+            };
+            let conversation = find_conversation(state, &payload.document_id, &conversation_id).await?;
+
+            // Store the question before asking Claude, so it survives a failed reply
+            let message_id = begin_exchange(
+                state,
+                &conversation_id,
+                conversation.active_leaf_id.as_deref(),
+                user_message.as_ref(),
+            )
+            .await?;
+            turn.set_reply(&conversation_id, &message_id).await;
+            (conversation, message_id)
+        }
+    };
+    let conversation_id = conversation.id.clone();
 
-            // Store in cache for future requests
-            state.pdf_cache.insert(payload.document_id.clone(), base64.clone()).await;
-            base64
+    // Build the transcript, either from the stored active branch, which now ends with
+    // the new user turn, or from the client. Only stored history is subject to rolling
+    // summarization
+    let (summary, transcript) = if payload.message.is_some() {
+        let stored = state
+            .chat_db
+            .get_message_path(&message_id)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+        let compacted = compact_history(state, &conversation, stored).await?;
+        (compacted.summary, to_transcript(compacted.messages))
+    } else {
+        let mut transcript: Vec<(String, String)> = payload
+            .messages
+            .iter()
+            .map(|m| (m.role.clone(), m.content.clone()))
+            .collect();
+        if let Some(user_message) = &user_message {
+            transcript.pop();
+            transcript.push(("user".to_string(), user_message.prompt()));
         }
+        (None, transcript)
     };
 
+    let (document_ids, request) =
+        build_chat_request(state, &conversation, &payload.document_ids, summary, transcript, params)
+            .await?;
+
+    Ok(PreparedChat {
+        conversation_id,
+        message_id,
+        document_ids,
+        request,
+    })
+}
+
+/// Convert stored messages into (role, content) pairs for the prompt
+pub fn to_transcript(messages: Vec<StoredMessage>) -> Vec<(String, String)> {
+    messages
+        .into_iter()
+        .map(|m| {
+            let content = message_prompt(&m);
+            (m.role, content)
+        })
+        .collect()
+}
+
+/// A stored message as sent to Claude, including any passage it asked about
+pub fn message_prompt(message: &StoredMessage) -> String {
+    match &message.selection {
+        Some(selection) => selection.annotate(&message.content),
+        None => message.content.clone(),
+    }
+}
+
+pub fn validate_selection(selection: &Selection) -> Result<(), ApiError> {
+    if selection.text.trim().is_empty() {
+        return Err(ApiError::BadRequest("Selected text must not be empty".to_string()));
+    }
+    if selection.text.chars().count() > MAX_SELECTION_CHARS {
+        return Err(ApiError::BadRequest(format!(
+            "Selected text must be at most {} characters",
+            MAX_SELECTION_CHARS
+        )));
+    }
+    if selection.page == 0 {
+        return Err(ApiError::BadRequest("Selection page numbers start at 1".to_string()));
+    }
+    if let Some(b) = selection.bounding_box {
+        let valid = [b.x, b.y, b.width, b.height].iter().all(|v| v.is_finite() && *v >= 0.0);
+        if !valid {
+            return Err(ApiError::BadRequest(
+                "Bounding box values must be non-negative numbers".to_string(),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Build the Claude request for a conversation from a transcript of (role, content)
+/// pairs, returning it with the ids of every attached document, primary first.
+pub async fn build_chat_request(
+    state: &AppState,
+    conversation: &Conversation,
+    extra_document_ids: &[String],
+    mut summary: Option<String>,
+    transcript: Vec<(String, String)>,
+    params: GenerationParams,
+) -> Result<(Vec<String>, ChatRequest), ApiError> {
+    // The primary document comes first, then any documents the conversation already
+    // covers, then newly requested ones
+    let mut document_ids = vec![conversation.document_id.clone()];
+    let recorded = state
+        .chat_db
+        .list_conversation_documents(&conversation.id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    for document_id in recorded.into_iter().chain(extra_document_ids.iter().cloned()) {
+        if !document_ids.contains(&document_id) {
+            document_ids.push(document_id);
+        }
+    }
+
+    let mut documents = Vec::with_capacity(document_ids.len());
+    for document_id in &document_ids {
+        let source = load_pdf_source(state, document_id).await?;
+        state
+            .chat_db
+            .ensure_document(document_id)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+        let filename = state
+            .chat_db
+            .get_document(document_id)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?
+            .map(|d| d.filename);
+        documents.push((filename, source));
+    }
+
+    state
+        .chat_db
+        .add_conversation_documents(&conversation.id, &document_ids)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
     let mut messages = Vec::new();
+    let mut documents = Some(documents);
 
     // Build conversation history
-    for (idx, msg) in payload.messages.iter().enumerate() {
-        if idx == 0 && msg.role == "user" {
-            // First message: include PDF with cache control enabled
-            messages.push(
-                state
-                    .claude
-                    .create_pdf_message(pdf_base64.clone(), msg.content.clone(), true),
-            );
+    for (idx, (role, content)) in transcript.into_iter().enumerate() {
+        if idx == 0 && role == "user" {
+            // First message: include the PDFs with cache control and citations enabled,
+            // followed by the summary of any turns that are no longer sent verbatim
+            let content = match summary.take() {
+                Some(summary) => format!(
+                    "<conversation_summary>\n{}\n</conversation_summary>\n\n{}",
+                    summary, content
+                ),
+                None => content,
+            };
+            let documents = documents.take().unwrap_or_default();
+            messages.push(state.claude.create_documents_message(documents, content, true, true));
         } else {
             // Subsequent messages: text only
-            messages.push(state.claude.create_text_message(&msg.role, msg.content.clone()));
+            messages.push(state.claude.create_text_message(&role, content));
         }
     }
 
+    let role_prompt = match &conversation.preset_id {
+        Some(preset_id) => state
+            .chat_db
+            .get_preset(preset_id)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?
+            .map(|preset| preset.system_prompt),
+        None => None,
+    };
+    let mut system_prompt = format!(
+        "{}\n\n{}",
+        role_prompt.as_deref().unwrap_or(DEFAULT_ROLE_PROMPT),
+        GUIDELINES_PROMPT
+    );
+    if document_ids.len() > 1 {
+        system_prompt = format!("{}\n{}", system_prompt, MULTI_DOCUMENT_PROMPT);
+    }
+
     // Create system prompt with cache control
     let system = Some(vec![SystemBlock {
         block_type: "text".to_string(),
-        text: SYSTEM_PROMPT.to_string(),
+        text: system_prompt,
         cache_control: Some(crate::claude::types::CacheControl {
             cache_type: "ephemeral".to_string(),
         }),
     }]);
 
     let request = ChatRequest {
-        model: "claude-sonnet-4-5-20250929".to_string(),
-        max_tokens: 4096,
+        model: params.model,
+        max_tokens: params.max_tokens,
         messages,
         system,
+        temperature: params.temperature,
+        stop_sequences: params.stop_sequences,
+        thinking: params
+            .thinking_budget
+            .map(|budget_tokens| ThinkingConfig::Enabled { budget_tokens }),
+        ..Default::default()
     };
 
-    let response = state
-        .claude
-        .chat(request)
-        .await
-        .map_err(|e| ApiError::ExternalApiError(format!("Claude API error: {}", e)))?;
-
-    // Extract text from response
-    let text = response
-        .content
-        .iter()
-        .filter_map(|c| match c {
-            ResponseContent::Text { text } => Some(text.clone()),
-        })
-        .collect::<Vec<_>>()
-        .join("\n");
+    Ok((document_ids, request))
+}
 
-    // Save the user message and assistant response to database
-    // Get the last user message from the payload
-    if let Some(last_user_msg) = payload.messages.last() {
-        if last_user_msg.role == "user" {
-            state
-                .chat_db
-                .save_message(&conversation_id, "user", &last_user_msg.content)
-                .await
-                .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+/// Assemble the answer text, page citations and quoted passages from a response, along
+/// with Claude's reasoning if `include_thinking` is set
+pub fn collect_reply(
+    content: Vec<ResponseContent>,
+    document_ids: &[String],
+    model: String,
+    tool_calls: Vec<ToolCall>,
+    include_thinking: bool,
+    stop_reason: Option<StopReason>,
+) -> AssistantReply {
+    // With citations enabled the answer is split into several text blocks, so they
+    // are joined back together as-is
+    let mut text = String::new();
+    let mut thinking: Vec<String> = Vec::new();
+    let mut quotes = Vec::new();
+    for block in content {
+        match block {
+            ResponseContent::Text {
+                text: block,
+                citations,
+            } => {
+                let start = text.chars().count();
+                text.push_str(&block);
+                let end = text.chars().count();
+                quotes.extend(
+                    citations
+                        .unwrap_or_default()
+                        .into_iter()
+                        .map(|c| DocumentQuote::from_citation(c, document_ids, start, end)),
+                );
+            }
+            ResponseContent::Thinking { thinking: block, .. } => thinking.push(block),
+            // Tool calls are handled by the tool loop, redacted reasoning is encrypted
+            // and other blocks carry nothing to show
+            _ => {}
         }
     }
 
-    // Save assistant response
+    AssistantReply {
+        citations: parse_page_citations(&text),
+        text,
+        quotes,
+        model,
+        tool_calls,
+        thinking: include_thinking.then(|| thinking.join("\n\n")),
+        stop_reason,
+    }
+}
+
+/// Store a new exchange: the user turn, if any, and a pending assistant message below
+/// it. Returns the id of the pending message.
+///
+/// The exchange is attached below `parent_message_id` and becomes the conversation's
+/// active branch.
+pub async fn begin_exchange(
+    state: &AppState,
+    conversation_id: &str,
+    parent_message_id: Option<&str>,
+    user_message: Option<&UserTurn>,
+) -> Result<String, ApiError> {
+    let mut parent_message_id = parent_message_id.map(str::to_string);
+
+    if let Some(user_message) = user_message {
+        let user_message_id = state
+            .chat_db
+            .save_message(NewMessage {
+                conversation_id,
+                parent_message_id: parent_message_id.as_deref(),
+                role: "user",
+                content: &user_message.content,
+                selection: user_message.selection.as_ref(),
+                ..Default::default()
+            })
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+        parent_message_id = Some(user_message_id);
+    }
+
+    let message_id = state
+        .chat_db
+        .save_message(NewMessage {
+            conversation_id,
+            parent_message_id: parent_message_id.as_deref(),
+            role: "assistant",
+            content: "",
+            status: MessageStatus::Pending,
+            ..Default::default()
+        })
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    state
+        .chat_db
+        .set_active_leaf(conversation_id, &message_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    Ok(message_id)
+}
+
+/// Fill in the pending assistant message of an exchange with Claude's reply
+pub async fn complete_exchange(
+    state: &Arc<AppState>,
+    conversation_id: &str,
+    message_id: &str,
+    reply: &AssistantReply,
+) -> Result<(), ApiError> {
     state
         .chat_db
-        .save_message(&conversation_id, "assistant", &text)
+        .complete_message(
+            message_id,
+            CompletedMessage {
+                content: &reply.text,
+                citations: &reply.citations,
+                quotes: &reply.quotes,
+                model: &reply.model,
+                tool_calls: (!reply.tool_calls.is_empty()).then_some(reply.tool_calls.as_slice()),
+                thinking: reply.thinking.as_deref(),
+            },
+        )
         .await
         .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
 
-    Ok(Json(ChatApiResponse {
-        response: text,
-        usage: Some(response.usage),
-    }))
+    spawn_title_generation(state.clone(), conversation_id.to_string());
+
+    Ok(())
+}
+
+/// Mark the pending assistant message of an exchange as failed so it can be retried
+pub async fn fail_exchange(state: &AppState, message_id: &str, error: &str) {
+    if let Err(e) = state.chat_db.fail_message(message_id, error).await {
+        eprintln!("Failed to mark message {} as failed: {}", message_id, e);
+    }
+}
+
+fn json_event<T: Serialize>(name: &str, data: &T) -> Event {
+    Event::default()
+        .event(name)
+        .json_data(data)
+        .unwrap_or_else(|e| error_event(&e.to_string()))
+}
+
+/// Why the reply ended, and whether it was cut off by `max_tokens`
+fn stop_event(stop_reason: Option<StopReason>) -> Event {
+    json_event(
+        "stop",
+        &serde_json::json!({
+            "stop_reason": stop_reason,
+            "truncated": stop_reason == Some(StopReason::MaxTokens),
+        }),
+    )
+}
+
+fn error_event(message: &str) -> Event {
+    Event::default()
+        .event("error")
+        .data(serde_json::json!({ "message": message }).to_string())
 }
 
 pub async fn get_chat_history_handler(
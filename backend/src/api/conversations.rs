@@ -0,0 +1,290 @@
+use crate::api::presets::find_preset;
+use crate::api::usage::{record_usage, UsageContext};
+use crate::api::AppState;
+use crate::db::{Conversation, StoredMessage};
+use crate::error::ApiError;
+use crate::models::MessageStatus;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::sync::Arc;
+
+#[derive(Debug, Deserialize)]
+pub struct CreateConversationRequest {
+    pub title: Option<String>,
+    /// Prompt preset to use instead of the default system prompt
+    pub preset_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateConversationRequest {
+    pub title: Option<String>,
+    /// Whether older turns may be folded into a rolling summary
+    pub summarization_enabled: Option<bool>,
+    /// Prompt preset to switch to; `null` goes back to the default prompt
+    #[serde(default, deserialize_with = "present")]
+    pub preset_id: Option<Option<String>>,
+}
+
+/// Distinguish a field set to `null` (`Some(None)`) from a missing one (`None`)
+fn present<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+#[derive(Debug, Serialize)]
+pub struct ConversationWithMessages {
+    #[serde(flatten)]
+    pub conversation: Conversation,
+    pub document_ids: Vec<String>,
+    pub messages: Vec<StoredMessage>,
+}
+
+/// List all conversations for a document, most recently updated first
+pub async fn list_conversations_handler(
+    State(state): State<Arc<AppState>>,
+    Path(document_id): Path<String>,
+) -> Result<Json<Vec<Conversation>>, ApiError> {
+    let conversations = state
+        .chat_db
+        .list_conversations(&document_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    Ok(Json(conversations))
+}
+
+/// Start a new conversation on a document
+pub async fn create_conversation_handler(
+    State(state): State<Arc<AppState>>,
+    Path(document_id): Path<String>,
+    Json(payload): Json<CreateConversationRequest>,
+) -> Result<(StatusCode, Json<Conversation>), ApiError> {
+    let exists = state
+        .storage
+        .exists(&document_id)
+        .await
+        .map_err(|e| ApiError::StorageError(e.to_string()))?;
+    if !exists {
+        return Err(ApiError::NotFound(format!("Document not found: {}", document_id)));
+    }
+
+    if let Some(preset_id) = &payload.preset_id {
+        find_preset(&state, preset_id).await?;
+    }
+
+    state
+        .chat_db
+        .ensure_document(&document_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    let conversation_id = state
+        .chat_db
+        .create_conversation(
+            &document_id,
+            payload.title.as_deref(),
+            payload.preset_id.as_deref(),
+        )
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    let conversation = find_conversation(&state, &document_id, &conversation_id).await?;
+
+    Ok((StatusCode::CREATED, Json(conversation)))
+}
+
+/// Get a conversation together with its messages
+pub async fn get_conversation_handler(
+    State(state): State<Arc<AppState>>,
+    Path((document_id, conversation_id)): Path<(String, String)>,
+) -> Result<Json<ConversationWithMessages>, ApiError> {
+    let conversation = find_conversation(&state, &document_id, &conversation_id).await?;
+
+    let messages = state
+        .chat_db
+        .get_conversation_messages_by_id(&conversation_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    let document_ids = state
+        .chat_db
+        .list_conversation_documents(&conversation_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    Ok(Json(ConversationWithMessages {
+        conversation,
+        document_ids,
+        messages,
+    }))
+}
+
+/// Rename a conversation or change its settings
+pub async fn update_conversation_handler(
+    State(state): State<Arc<AppState>>,
+    Path((document_id, conversation_id)): Path<(String, String)>,
+    Json(payload): Json<UpdateConversationRequest>,
+) -> Result<Json<Conversation>, ApiError> {
+    find_conversation(&state, &document_id, &conversation_id).await?;
+
+    if let Some(title) = &payload.title {
+        let title = title.trim();
+        if title.is_empty() {
+            return Err(ApiError::BadRequest("Title must not be empty".to_string()));
+        }
+
+        state
+            .chat_db
+            .update_conversation_title(&conversation_id, title)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    }
+
+    if let Some(preset_id) = &payload.preset_id {
+        if let Some(preset_id) = preset_id {
+            find_preset(&state, preset_id).await?;
+        }
+
+        state
+            .chat_db
+            .set_conversation_preset(&conversation_id, preset_id.as_deref())
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    }
+
+    if let Some(enabled) = payload.summarization_enabled {
+        state
+            .chat_db
+            .set_summarization_enabled(&conversation_id, enabled)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    }
+
+    let conversation = find_conversation(&state, &document_id, &conversation_id).await?;
+
+    Ok(Json(conversation))
+}
+
+/// Delete a conversation and all of its messages
+pub async fn delete_conversation_handler(
+    State(state): State<Arc<AppState>>,
+    Path((document_id, conversation_id)): Path<(String, String)>,
+) -> Result<StatusCode, ApiError> {
+    find_conversation(&state, &document_id, &conversation_id).await?;
+
+    state
+        .chat_db
+        .delete_conversation(&conversation_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Regenerate a conversation's title from its first exchange
+pub async fn regenerate_title_handler(
+    State(state): State<Arc<AppState>>,
+    Path((document_id, conversation_id)): Path<(String, String)>,
+) -> Result<Json<Conversation>, ApiError> {
+    find_conversation(&state, &document_id, &conversation_id).await?;
+
+    let title = generate_title(&state, &conversation_id)
+        .await?
+        .ok_or_else(|| ApiError::BadRequest("Conversation has no completed exchange yet".to_string()))?;
+
+    state
+        .chat_db
+        .update_conversation_title(&conversation_id, &title)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    let conversation = find_conversation(&state, &document_id, &conversation_id).await?;
+
+    Ok(Json(conversation))
+}
+
+/// Title an untitled conversation in the background once it has a first exchange
+pub fn spawn_title_generation(state: Arc<AppState>, conversation_id: String) {
+    tokio::spawn(async move {
+        let untitled = matches!(
+            state.chat_db.get_conversation(&conversation_id).await,
+            Ok(Some(ref c)) if c.title.is_none()
+        );
+        if !untitled {
+            return;
+        }
+
+        match generate_title(&state, &conversation_id).await {
+            Ok(Some(title)) => {
+                // A title set by the user in the meantime takes precedence
+                if let Err(e) = state.chat_db.set_title_if_unset(&conversation_id, &title).await {
+                    eprintln!("Failed to save title for {}: {}", conversation_id, e);
+                }
+            }
+            Ok(None) => {}
+            Err(e) => eprintln!("Failed to generate title for {}: {}", conversation_id, e),
+        }
+    });
+}
+
+/// Ask Claude for a title based on the first user question and assistant reply
+async fn generate_title(state: &AppState, conversation_id: &str) -> Result<Option<String>, ApiError> {
+    let messages = state
+        .chat_db
+        .get_conversation_messages_by_id(conversation_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    // Only a finished exchange says what the conversation is about
+    let complete = || messages.iter().filter(|m| m.status == MessageStatus::Complete);
+    let question = complete().find(|m| m.role == "user");
+    let answer = complete().find(|m| m.role == "assistant");
+
+    let (Some(question), Some(answer)) = (question, answer) else {
+        return Ok(None);
+    };
+
+    let (title, usage) = state
+        .claude
+        .generate_title(&question.content, &answer.content)
+        .await
+        .map_err(|e| ApiError::UpstreamError(format!("Claude API error: {}", e)))?;
+
+    let document_id = state
+        .chat_db
+        .get_conversation(conversation_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?
+        .map(|c| c.document_id);
+    let context = UsageContext {
+        kind: "title",
+        conversation_id: Some(conversation_id),
+        document_id: document_id.as_deref(),
+        ..Default::default()
+    };
+    record_usage(state, context, state.claude.model(), &usage).await;
+
+    Ok(Some(title))
+}
+
+/// Look up a conversation, ensuring it belongs to the given document
+pub async fn find_conversation(
+    state: &AppState,
+    document_id: &str,
+    conversation_id: &str,
+) -> Result<Conversation, ApiError> {
+    state
+        .chat_db
+        .get_conversation(conversation_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?
+        .filter(|c| c.document_id == document_id)
+        .ok_or_else(|| ApiError::NotFound(format!("Conversation not found: {}", conversation_id)))
+}
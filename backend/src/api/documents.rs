@@ -1,4 +1,5 @@
 use crate::api::AppState;
+use crate::db::Document;
 use crate::error::ApiError;
 use axum::{
     extract::{Path, Query, State},
@@ -27,6 +28,30 @@ pub struct DocumentWithMetadata {
     pub uploaded_at: String,
 }
 
+impl From<Document> for DocumentWithMetadata {
+    fn from(doc: Document) -> Self {
+        let keywords = doc
+            .keywords
+            .as_ref()
+            .and_then(|k| serde_json::from_str(k).ok())
+            .unwrap_or_default();
+
+        let topics = doc
+            .topics
+            .as_ref()
+            .and_then(|t| serde_json::from_str(t).ok())
+            .unwrap_or_default();
+
+        Self {
+            id: doc.id,
+            filename: doc.filename,
+            keywords,
+            topics,
+            uploaded_at: doc.uploaded_at,
+        }
+    }
+}
+
 pub async fn get_document_handler(
     State(state): State<Arc<AppState>>,
     Path(document_id): Path<String>,
@@ -53,30 +78,5 @@ pub async fn list_documents_handler(
         .await
         .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Database error: {}", e)))?;
 
-    let documents_with_metadata = documents
-        .into_iter()
-        .map(|doc| {
-            let keywords = doc
-                .keywords
-                .as_ref()
-                .and_then(|k| serde_json::from_str(k).ok())
-                .unwrap_or_else(Vec::new);
-
-            let topics = doc
-                .topics
-                .as_ref()
-                .and_then(|t| serde_json::from_str(t).ok())
-                .unwrap_or_else(Vec::new);
-
-            DocumentWithMetadata {
-                id: doc.id,
-                filename: doc.filename,
-                keywords,
-                topics,
-                uploaded_at: doc.uploaded_at,
-            }
-        })
-        .collect();
-
-    Ok(Json(documents_with_metadata))
+    Ok(Json(documents.into_iter().map(Into::into).collect()))
 }
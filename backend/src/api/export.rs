@@ -0,0 +1,473 @@
+use crate::api::documents::DocumentWithMetadata;
+use crate::api::AppState;
+use crate::db::{Conversation, StoredMessage};
+use crate::error::ApiError;
+use crate::models::{parse_page_citations, Citation, MessageStatus, Selection};
+use axum::{
+    extract::{Path, Query, State},
+    http::header,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+use std::sync::Arc;
+
+#[derive(Debug, Deserialize)]
+pub struct ExportQuery {
+    #[serde(default)]
+    pub format: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ExportFormat {
+    Markdown,
+    Html,
+    Json,
+}
+
+impl ExportFormat {
+    fn parse(format: Option<&str>) -> Result<Self, ApiError> {
+        match format.unwrap_or("md") {
+            "md" | "markdown" => Ok(Self::Markdown),
+            "html" => Ok(Self::Html),
+            "json" => Ok(Self::Json),
+            other => Err(ApiError::BadRequest(format!(
+                "Unsupported export format: {} (use md, html or json)",
+                other
+            ))),
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            Self::Markdown => "text/markdown; charset=utf-8",
+            Self::Html => "text/html; charset=utf-8",
+            Self::Json => "application/json",
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Markdown => "md",
+            Self::Html => "html",
+            Self::Json => "json",
+        }
+    }
+}
+
+/// A conversation transcript with everything needed to read it on its own
+#[derive(Debug, Serialize)]
+pub struct ConversationExport {
+    pub conversation: Conversation,
+    /// Documents the conversation covers, primary document first
+    pub documents: Vec<DocumentWithMetadata>,
+    /// The active branch, oldest message first
+    pub messages: Vec<ExportedMessage>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExportedMessage {
+    pub id: String,
+    pub role: String,
+    pub content: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub selection: Option<Selection>,
+    pub page_references: Vec<PageReference>,
+    pub created_at: String,
+}
+
+/// A page reference in a message, resolved to links into the document
+#[derive(Debug, Serialize)]
+pub struct PageReference {
+    /// Character offsets of the reference in the content, end exclusive
+    pub start: usize,
+    pub end: usize,
+    pub document_id: String,
+    pub pages: Vec<PageAnchor>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PageAnchor {
+    pub page: u32,
+    pub href: String,
+}
+
+/// Export a conversation's active branch as Markdown, HTML or JSON
+pub async fn export_conversation_handler(
+    State(state): State<Arc<AppState>>,
+    Path(conversation_id): Path<String>,
+    Query(query): Query<ExportQuery>,
+) -> Result<Response, ApiError> {
+    let format = ExportFormat::parse(query.format.as_deref())?;
+    let export = build_export(&state, &conversation_id).await?;
+
+    let body = match format {
+        ExportFormat::Markdown => render_markdown(&export),
+        ExportFormat::Html => render_html(&export),
+        ExportFormat::Json => serde_json::to_string_pretty(&export)
+            .map_err(|e| ApiError::InternalError(e.to_string()))?,
+    };
+
+    let disposition = format!(
+        "attachment; filename=\"conversation-{}.{}\"",
+        conversation_id,
+        format.extension()
+    );
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, format.content_type().to_string()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+async fn build_export(state: &AppState, conversation_id: &str) -> Result<ConversationExport, ApiError> {
+    let conversation = state
+        .chat_db
+        .get_conversation(conversation_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?
+        .ok_or_else(|| ApiError::NotFound(format!("Conversation not found: {}", conversation_id)))?;
+
+    let mut document_ids = vec![conversation.document_id.clone()];
+    let attached = state
+        .chat_db
+        .list_conversation_documents(conversation_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    for document_id in attached {
+        if !document_ids.contains(&document_id) {
+            document_ids.push(document_id);
+        }
+    }
+
+    let mut documents = Vec::with_capacity(document_ids.len());
+    for document_id in &document_ids {
+        let document = state
+            .chat_db
+            .get_document(document_id)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+        if let Some(document) = document {
+            documents.push(DocumentWithMetadata::from(document));
+        }
+    }
+
+    let messages = state
+        .chat_db
+        .get_conversation_messages_by_id(conversation_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?
+        .into_iter()
+        // Replies that failed or are still being written have nothing to export
+        .filter(|message| message.status == MessageStatus::Complete)
+        .map(|message| export_message(message, &conversation.document_id, &documents))
+        .collect();
+
+    Ok(ConversationExport {
+        conversation,
+        documents,
+        messages,
+    })
+}
+
+fn export_message(
+    message: StoredMessage,
+    primary_document_id: &str,
+    documents: &[DocumentWithMetadata],
+) -> ExportedMessage {
+    let page_references = parse_page_citations(&message.content)
+        .into_iter()
+        .filter_map(|citation| resolve_reference(citation, primary_document_id, documents))
+        .collect();
+
+    ExportedMessage {
+        id: message.id,
+        role: message.role,
+        content: message.content,
+        model: message.model,
+        selection: message.selection.map(|selection| selection.0),
+        page_references,
+        created_at: message.created_at,
+    }
+}
+
+/// Link a parsed reference to the document it names, or the primary document if it
+/// names none.
+///
+/// A reference naming a document that is not attached to the conversation is left as
+/// plain text rather than linked to a page of the wrong document.
+fn resolve_reference(
+    citation: Citation,
+    primary_document_id: &str,
+    documents: &[DocumentWithMetadata],
+) -> Option<PageReference> {
+    let document_id = match &citation.document {
+        Some(name) => documents
+            .iter()
+            .find(|d| d.filename.eq_ignore_ascii_case(name.trim()))
+            .map(|d| d.id.clone())?,
+        None => primary_document_id.to_string(),
+    };
+
+    let pages = citation
+        .pages
+        .iter()
+        .map(|&page| PageAnchor {
+            page,
+            href: format!("/api/documents/{}#page={}", document_id, page),
+        })
+        .collect();
+
+    Some(PageReference {
+        start: citation.start,
+        end: citation.end,
+        document_id,
+        pages,
+    })
+}
+
+fn render_markdown(export: &ConversationExport) -> String {
+    let mut out = String::new();
+    let conversation = &export.conversation;
+
+    let _ = writeln!(out, "# {}\n", conversation.title.as_deref().unwrap_or("Conversation"));
+    let _ = writeln!(out, "- Conversation: `{}`", conversation.id);
+    let _ = writeln!(out, "- Created: {}", conversation.created_at);
+    let _ = writeln!(out, "- Updated: {}\n", conversation.updated_at);
+
+    out.push_str("## Documents\n\n");
+    for document in &export.documents {
+        let _ = writeln!(out, "### {}\n", document.filename);
+        let _ = writeln!(out, "- Document: [`{}`](/api/documents/{})", document.id, document.id);
+        let _ = writeln!(out, "- Uploaded: {}", document.uploaded_at);
+        let _ = writeln!(out, "- Keywords: {}", join_or_none(&document.keywords));
+        let _ = writeln!(out, "- Topics: {}\n", join_or_none(&document.topics));
+    }
+
+    out.push_str("## Transcript\n");
+    for message in &export.messages {
+        let _ = write!(out, "\n### {} · {}", role_label(&message.role), message.created_at);
+        if let Some(model) = &message.model {
+            let _ = write!(out, " · {}", model);
+        }
+        out.push_str("\n\n");
+
+        if let Some(selection) = &message.selection {
+            let _ = writeln!(out, "> Selected passage (page {}):", selection.page);
+            for line in selection.text.trim().lines() {
+                let _ = writeln!(out, "> {}", line);
+            }
+            out.push('\n');
+        }
+
+        let content = render_with_links(message, |text| text.to_string(), |reference, text| {
+            let links: Vec<String> = reference
+                .pages
+                .iter()
+                .map(|anchor| format!("[page {}]({})", anchor.page, anchor.href))
+                .collect();
+            match reference_document_label(text) {
+                Some(label) => format!("({}, {})", label, links.join(", ")),
+                None => format!("({})", links.join(", ")),
+            }
+        });
+        let _ = writeln!(out, "{}", content.trim_end());
+    }
+
+    out
+}
+
+fn render_html(export: &ConversationExport) -> String {
+    let mut out = String::new();
+    let conversation = &export.conversation;
+    let title = escape_html(conversation.title.as_deref().unwrap_or("Conversation"));
+
+    out.push_str("<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n");
+    let _ = writeln!(out, "<title>{}</title>", title);
+    out.push_str(
+        "<style>\
+         body{font-family:system-ui,sans-serif;max-width:48rem;margin:2rem auto;padding:0 1rem;line-height:1.5}\
+         .message{border-top:1px solid #ddd;padding:1rem 0}\
+         .meta{color:#666;font-size:.875rem}\
+         .content{white-space:pre-wrap}\
+         blockquote{margin:.5rem 0;padding-left:1rem;border-left:3px solid #ccc;color:#444;white-space:pre-wrap}\
+         </style>\n</head>\n<body>\n",
+    );
+
+    let _ = writeln!(out, "<h1>{}</h1>", title);
+    let _ = writeln!(
+        out,
+        "<p class=\"meta\">Conversation <code>{}</code> · created {} · updated {}</p>",
+        escape_html(&conversation.id),
+        escape_html(&conversation.created_at),
+        escape_html(&conversation.updated_at)
+    );
+
+    out.push_str("<h2>Documents</h2>\n");
+    for document in &export.documents {
+        let _ = writeln!(
+            out,
+            "<h3><a href=\"/api/documents/{}\">{}</a></h3>",
+            escape_html(&document.id),
+            escape_html(&document.filename)
+        );
+        let _ = writeln!(
+            out,
+            "<ul><li>Uploaded: {}</li><li>Keywords: {}</li><li>Topics: {}</li></ul>",
+            escape_html(&document.uploaded_at),
+            escape_html(&join_or_none(&document.keywords)),
+            escape_html(&join_or_none(&document.topics))
+        );
+    }
+
+    out.push_str("<h2>Transcript</h2>\n");
+    for message in &export.messages {
+        let _ = writeln!(out, "<div class=\"message {}\">", escape_html(&message.role));
+        let _ = write!(
+            out,
+            "<p class=\"meta\"><strong>{}</strong> · {}",
+            role_label(&message.role),
+            escape_html(&message.created_at)
+        );
+        if let Some(model) = &message.model {
+            let _ = write!(out, " · {}", escape_html(model));
+        }
+        out.push_str("</p>\n");
+
+        if let Some(selection) = &message.selection {
+            let _ = writeln!(
+                out,
+                "<blockquote>Selected passage (page {}):\n{}</blockquote>",
+                selection.page,
+                escape_html(selection.text.trim())
+            );
+        }
+
+        let content = render_with_links(message, escape_html, |reference, text| {
+            let links: Vec<String> = reference
+                .pages
+                .iter()
+                .map(|anchor| {
+                    format!("<a href=\"{}\">page {}</a>", escape_html(&anchor.href), anchor.page)
+                })
+                .collect();
+            match reference_document_label(text) {
+                Some(label) => format!("({}, {})", escape_html(label), links.join(", ")),
+                None => format!("({})", links.join(", ")),
+            }
+        });
+        let _ = writeln!(out, "<div class=\"content\">{}</div>\n</div>", content.trim_end());
+    }
+
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+/// Render message content, passing plain text through `plain` and each page reference,
+/// along with its original text, through `link`
+fn render_with_links(
+    message: &ExportedMessage,
+    plain: impl Fn(&str) -> String,
+    link: impl Fn(&PageReference, &str) -> String,
+) -> String {
+    let content = &message.content;
+    let mut out = String::new();
+    let mut cursor = 0;
+
+    for reference in &message.page_references {
+        let start = byte_offset(content, reference.start);
+        let end = byte_offset(content, reference.end);
+        out.push_str(&plain(&content[cursor..start]));
+        out.push_str(&link(reference, &content[start..end]));
+        cursor = end;
+    }
+    out.push_str(&plain(&content[cursor..]));
+
+    out
+}
+
+/// The document name in a reference such as "(paper.pdf, page 3)", if any
+fn reference_document_label(text: &str) -> Option<&str> {
+    let inner = text.trim_start_matches('(').trim_end_matches(')');
+    let (first, _) = inner.split_once(',')?;
+    let first = first.trim();
+    (!first.to_ascii_lowercase().starts_with("page")).then_some(first)
+}
+
+fn byte_offset(text: &str, char_offset: usize) -> usize {
+    text.char_indices()
+        .nth(char_offset)
+        .map_or(text.len(), |(idx, _)| idx)
+}
+
+fn role_label(role: &str) -> &'static str {
+    match role {
+        "user" => "User",
+        "assistant" => "Assistant",
+        _ => "Other",
+    }
+}
+
+fn join_or_none(values: &[String]) -> String {
+    if values.is_empty() {
+        "none".to_string()
+    } else {
+        values.join(", ")
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document(id: &str, filename: &str) -> DocumentWithMetadata {
+        DocumentWithMetadata {
+            id: id.to_string(),
+            filename: filename.to_string(),
+            keywords: Vec::new(),
+            topics: Vec::new(),
+            uploaded_at: "2024-01-01T00:00:00Z".to_string(),
+        }
+    }
+
+    fn references(content: &str) -> Vec<PageReference> {
+        let documents = [document("primary", "report.pdf"), document("other", "Appendix.pdf")];
+        parse_page_citations(content)
+            .into_iter()
+            .filter_map(|citation| resolve_reference(citation, "primary", &documents))
+            .collect()
+    }
+
+    #[test]
+    fn references_link_to_the_named_or_primary_document() {
+        let found = references("See (page 2) and (appendix.pdf, page 7).");
+        let hrefs: Vec<&str> = found.iter().flat_map(|r| r.pages.iter().map(|p| p.href.as_str())).collect();
+        assert_eq!(hrefs, ["/api/documents/primary#page=2", "/api/documents/other#page=7"]);
+    }
+
+    #[test]
+    fn unknown_document_names_are_left_unlinked() {
+        assert!(references("As (missing.pdf, page 4) shows").is_empty());
+    }
+}
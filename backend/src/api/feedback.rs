@@ -0,0 +1,121 @@
+use crate::api::conversations::find_conversation;
+use crate::api::AppState;
+use crate::db::{Feedback, RatedExchange};
+use crate::error::ApiError;
+use crate::models::{FeedbackCategory, FeedbackRequest, MessageStatus, Rating};
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use serde::Deserialize;
+use std::sync::Arc;
+
+const MAX_COMMENT_CHARS: usize = 2000;
+
+#[derive(Debug, Deserialize)]
+pub struct FeedbackExportQuery {
+    pub rating: Option<Rating>,
+    pub category: Option<FeedbackCategory>,
+    pub document_id: Option<String>,
+}
+
+/// Rate an assistant message, replacing any earlier rating
+pub async fn set_feedback_handler(
+    State(state): State<Arc<AppState>>,
+    Path((document_id, conversation_id, message_id)): Path<(String, String, String)>,
+    Json(payload): Json<FeedbackRequest>,
+) -> Result<Json<Feedback>, ApiError> {
+    find_assistant_message(&state, &document_id, &conversation_id, &message_id).await?;
+
+    let comment = payload
+        .comment
+        .as_deref()
+        .map(str::trim)
+        .filter(|c| !c.is_empty());
+    if comment.is_some_and(|c| c.chars().count() > MAX_COMMENT_CHARS) {
+        return Err(ApiError::BadRequest(format!(
+            "Comment must be at most {} characters",
+            MAX_COMMENT_CHARS
+        )));
+    }
+
+    let feedback = state
+        .chat_db
+        .set_feedback(&message_id, payload.rating, payload.category, comment)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    Ok(Json(feedback))
+}
+
+/// Get the rating of an assistant message
+pub async fn get_feedback_handler(
+    State(state): State<Arc<AppState>>,
+    Path((document_id, conversation_id, message_id)): Path<(String, String, String)>,
+) -> Result<Json<Feedback>, ApiError> {
+    find_assistant_message(&state, &document_id, &conversation_id, &message_id).await?;
+
+    let feedback = state
+        .chat_db
+        .get_feedback(&message_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?
+        .ok_or_else(|| ApiError::NotFound(format!("No feedback for message: {}", message_id)))?;
+
+    Ok(Json(feedback))
+}
+
+/// Remove the rating of an assistant message
+pub async fn delete_feedback_handler(
+    State(state): State<Arc<AppState>>,
+    Path((document_id, conversation_id, message_id)): Path<(String, String, String)>,
+) -> Result<StatusCode, ApiError> {
+    find_assistant_message(&state, &document_id, &conversation_id, &message_id).await?;
+
+    state
+        .chat_db
+        .delete_feedback(&message_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Export rated question/answer pairs, e.g. to build an evaluation set
+pub async fn export_feedback_handler(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<FeedbackExportQuery>,
+) -> Result<Json<Vec<RatedExchange>>, ApiError> {
+    let exchanges = state
+        .chat_db
+        .list_rated_exchanges(query.rating, query.category, query.document_id.as_deref())
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    Ok(Json(exchanges))
+}
+
+async fn find_assistant_message(
+    state: &AppState,
+    document_id: &str,
+    conversation_id: &str,
+    message_id: &str,
+) -> Result<(), ApiError> {
+    find_conversation(state, document_id, conversation_id).await?;
+
+    let message = state
+        .chat_db
+        .get_message(conversation_id, message_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?
+        .ok_or_else(|| ApiError::NotFound(format!("Message not found: {}", message_id)))?;
+
+    if message.role != "assistant" || message.status != MessageStatus::Complete {
+        return Err(ApiError::BadRequest(
+            "Only complete assistant messages can be rated".to_string(),
+        ));
+    }
+
+    Ok(())
+}
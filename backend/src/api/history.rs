@@ -0,0 +1,246 @@
+use crate::api::chat::message_prompt;
+use crate::api::usage::{record_usage, UsageContext};
+use crate::api::AppState;
+use crate::config::HistoryPolicy;
+use crate::db::{Conversation, StoredMessage};
+use crate::error::ApiError;
+use crate::models::MessageStatus;
+
+/// Stored history to send with the next turn, after rolling summarization
+pub struct CompactedHistory {
+    /// Summary of the turns that are no longer sent verbatim
+    pub summary: Option<String>,
+    pub messages: Vec<StoredMessage>,
+}
+
+/// Rough token estimate; close enough to decide when to summarize
+fn estimate_tokens(text: &str) -> usize {
+    text.len() / 4
+}
+
+/// Which stored messages to send verbatim and which to fold into the summary
+struct HistoryPlan {
+    /// Summary that still applies to this branch, before folding anything new into it
+    summary: Option<String>,
+    kept: Vec<StoredMessage>,
+    /// Oldest messages to fold into the summary; empty when the history fits
+    folded: Vec<StoredMessage>,
+}
+
+/// Decide how much of the history to send, without calling Claude.
+///
+/// Messages already folded into the summary are dropped. If what remains exceeds the
+/// token budget, everything but the last `keep_turns` turns is marked for folding,
+/// cutting at a user message so the kept history still starts with a user turn.
+fn plan_history(conversation: &Conversation, history: Vec<StoredMessage>, policy: &HistoryPolicy) -> HistoryPlan {
+    // Pending and failed replies have nothing to send
+    let history: Vec<StoredMessage> = history
+        .into_iter()
+        .filter(|m| m.status == MessageStatus::Complete)
+        .collect();
+
+    if !conversation.summarization_enabled {
+        return HistoryPlan {
+            summary: None,
+            kept: history,
+            folded: Vec::new(),
+        };
+    }
+
+    // The stored summary only applies if the last message it covers is on this path;
+    // a branch that forked earlier is sent without it
+    let (summary, mut messages): (Option<String>, Vec<StoredMessage>) =
+        match &conversation.summarized_until {
+            Some(until) if history.iter().any(|m| &m.created_at == until) => (
+                conversation.summary.clone(),
+                history.into_iter().filter(|m| &m.created_at > until).collect(),
+            ),
+            _ => (None, history),
+        };
+
+    let estimated = summary.as_deref().map_or(0, estimate_tokens)
+        + messages.iter().map(|m| estimate_tokens(&m.content)).sum::<usize>();
+    let user_indices: Vec<usize> = messages
+        .iter()
+        .enumerate()
+        .filter(|(_, m)| m.role == "user")
+        .map(|(idx, _)| idx)
+        .collect();
+    let keep_turns = policy.keep_turns.max(1);
+    if estimated <= policy.token_budget || user_indices.len() <= keep_turns {
+        return HistoryPlan {
+            summary,
+            kept: messages,
+            folded: Vec::new(),
+        };
+    }
+
+    let kept = messages.split_off(user_indices[user_indices.len() - keep_turns]);
+    HistoryPlan {
+        summary,
+        kept,
+        folded: messages,
+    }
+}
+
+/// Apply the rolling summarization strategy to a conversation's stored history.
+///
+/// When [`plan_history`] finds turns to fold, they are summarized via Claude together
+/// with the existing summary before the history is returned.
+pub async fn compact_history(
+    state: &AppState,
+    conversation: &Conversation,
+    history: Vec<StoredMessage>,
+) -> Result<CompactedHistory, ApiError> {
+    let HistoryPlan { mut summary, kept, folded } = plan_history(conversation, history, &state.history_policy);
+    if folded.is_empty() {
+        return Ok(CompactedHistory { summary, messages: kept });
+    }
+
+    let transcript = folded
+        .iter()
+        .map(|m| format!("{}: {}", m.role, message_prompt(m)))
+        .collect::<Vec<_>>()
+        .join("\n\n");
+
+    let (updated, usage) = state
+        .claude
+        .summarize_conversation(summary.as_deref(), &transcript)
+        .await
+        .map_err(|e| ApiError::UpstreamError(format!("Claude API error: {}", e)))?;
+
+    let context = UsageContext {
+        kind: "summary",
+        conversation_id: Some(&conversation.id),
+        document_id: Some(&conversation.document_id),
+        ..Default::default()
+    };
+    record_usage(state, context, state.claude.model(), &usage).await;
+
+    if let Some(last) = folded.last() {
+        state
+            .chat_db
+            .update_conversation_summary(&conversation.id, &updated, &last.created_at)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    }
+    summary = Some(updated);
+
+    Ok(CompactedHistory {
+        summary,
+        messages: kept,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conversation(summarization_enabled: bool) -> Conversation {
+        Conversation {
+            id: "conversation".to_string(),
+            document_id: "document".to_string(),
+            title: None,
+            summary: None,
+            summarized_until: None,
+            summarization_enabled,
+            active_leaf_id: None,
+            preset_id: None,
+            created_at: "2024-01-01T00:00:00Z".to_string(),
+            updated_at: "2024-01-01T00:00:00Z".to_string(),
+        }
+    }
+
+    fn message(index: usize, role: &str, content: &str) -> StoredMessage {
+        StoredMessage {
+            id: format!("m{index}"),
+            parent_message_id: None,
+            role: role.to_string(),
+            content: content.to_string(),
+            citations: None,
+            quotes: None,
+            model: None,
+            tool_calls: None,
+            selection: None,
+            status: MessageStatus::Complete,
+            error: None,
+            thinking: None,
+            created_at: format!("2024-01-01T00:00:{index:02}Z"),
+        }
+    }
+
+    /// `turns` question/answer pairs of `size` bytes each
+    fn turns(turns: usize, size: usize) -> Vec<StoredMessage> {
+        (0..turns * 2)
+            .map(|i| message(i, if i % 2 == 0 { "user" } else { "assistant" }, &"x".repeat(size)))
+            .collect()
+    }
+
+    fn policy(keep_turns: usize, token_budget: usize) -> HistoryPolicy {
+        HistoryPolicy { keep_turns, token_budget }
+    }
+
+    fn ids(messages: &[StoredMessage]) -> Vec<&str> {
+        messages.iter().map(|m| m.id.as_str()).collect()
+    }
+
+    #[test]
+    fn history_within_budget_is_sent_whole() {
+        let plan = plan_history(&conversation(true), turns(5, 40), &policy(2, 1000));
+        assert_eq!(plan.kept.len(), 10);
+        assert!(plan.folded.is_empty());
+    }
+
+    #[test]
+    fn old_turns_over_budget_are_folded() {
+        let plan = plan_history(&conversation(true), turns(5, 400), &policy(2, 500));
+        assert_eq!(ids(&plan.folded), ["m0", "m1", "m2", "m3", "m4", "m5"]);
+        assert_eq!(ids(&plan.kept), ["m6", "m7", "m8", "m9"]);
+    }
+
+    #[test]
+    fn fewer_turns_than_keep_turns_are_never_folded() {
+        let plan = plan_history(&conversation(true), turns(2, 4000), &policy(3, 100));
+        assert_eq!(plan.kept.len(), 4);
+        assert!(plan.folded.is_empty());
+    }
+
+    #[test]
+    fn single_oversized_message_is_kept() {
+        let history = vec![message(0, "user", &"x".repeat(40_000))];
+        let plan = plan_history(&conversation(true), history, &policy(0, 100));
+        assert_eq!(ids(&plan.kept), ["m0"]);
+        assert!(plan.folded.is_empty());
+    }
+
+    #[test]
+    fn disabled_summarization_sends_everything_without_the_summary() {
+        let mut conversation = conversation(false);
+        conversation.summary = Some("Earlier turns".to_string());
+        conversation.summarized_until = Some("2024-01-01T00:00:03Z".to_string());
+
+        let plan = plan_history(&conversation, turns(5, 400), &policy(2, 500));
+        assert_eq!(plan.summary, None);
+        assert_eq!(plan.kept.len(), 10);
+        assert!(plan.folded.is_empty());
+    }
+
+    #[test]
+    fn stored_summary_drops_the_messages_it_covers() {
+        let mut conversation = conversation(true);
+        conversation.summary = Some("Earlier turns".to_string());
+        conversation.summarized_until = Some("2024-01-01T00:00:03Z".to_string());
+
+        let plan = plan_history(&conversation, turns(3, 40), &policy(2, 1000));
+        assert_eq!(plan.summary.as_deref(), Some("Earlier turns"));
+        assert_eq!(ids(&plan.kept), ["m4", "m5"]);
+    }
+
+    #[test]
+    fn unfinished_replies_are_left_out() {
+        let mut history = turns(2, 40);
+        history[3].status = MessageStatus::Failed;
+        let plan = plan_history(&conversation(true), history, &policy(2, 1000));
+        assert_eq!(ids(&plan.kept), ["m0", "m1", "m2"]);
+    }
+}
@@ -1,3 +1,5 @@
+use crate::api::usage::{record_usage, UsageContext};
+use crate::api::pdf::with_pdf_source;
 use crate::api::AppState;
 use crate::error::ApiError;
 use axum::{
@@ -6,7 +8,6 @@ use axum::{
 };
 use serde::Serialize;
 use std::sync::Arc;
-use std::time::Duration;
 
 #[derive(Serialize)]
 pub struct BackfillResponse {
@@ -28,11 +29,16 @@ pub async fn backfill_metadata_handler(
 
 /// Extract keywords and topics from a PDF and save to database
 pub async fn extract_and_save_metadata(state: &Arc<AppState>, document_id: &str) -> anyhow::Result<()> {
-    // Get PDF as base64
-    let pdf_base64 = state.storage.get_pdf_base64(document_id).await?;
+    // Extract metadata using Claude, sending the PDF as base64 or as a Files API upload
+    let (metadata, usage) =
+        with_pdf_source(state, document_id, |source| state.claude.extract_metadata(source)).await?;
 
-    // Extract metadata using Claude
-    let metadata = state.claude.extract_metadata(pdf_base64).await?;
+    let context = UsageContext {
+        kind: "metadata",
+        document_id: Some(document_id),
+        ..Default::default()
+    };
+    record_usage(state, context, state.claude.model(), &usage).await;
 
     // Save to database as JSON arrays
     let keywords_json = serde_json::to_string(&metadata.keywords)?;
@@ -68,15 +74,15 @@ pub async fn backfill_metadata(state: &Arc<AppState>) -> anyhow::Result<Backfill
         processed += 1;
         println!("Processing document {} ({})...", doc.id, doc.filename);
 
-        // Retry with exponential backoff
-        match retry_with_backoff(|| extract_and_save_metadata(state, &doc.id), 3).await {
+        // The Claude client retries rate limits and overload itself
+        match extract_and_save_metadata(state, &doc.id).await {
             Ok(_) => {
                 succeeded += 1;
                 println!("Successfully processed {}", doc.id);
             }
             Err(e) => {
                 failed += 1;
-                eprintln!("Failed to process {} after retries: {}", doc.id, e);
+                eprintln!("Failed to process {}: {}", doc.id, e);
             }
         }
     }
@@ -87,34 +93,3 @@ pub async fn backfill_metadata(state: &Arc<AppState>) -> anyhow::Result<Backfill
         failed,
     })
 }
-
-/// Retry a function with exponential backoff
-async fn retry_with_backoff<F, Fut, T>(
-    mut f: F,
-    max_retries: u32,
-) -> anyhow::Result<T>
-where
-    F: FnMut() -> Fut,
-    Fut: std::future::Future<Output = anyhow::Result<T>>,
-{
-    let mut retry_count = 0;
-    let mut delay = Duration::from_secs(1);
-
-    loop {
-        match f().await {
-            Ok(result) => return Ok(result),
-            Err(e) => {
-                retry_count += 1;
-                if retry_count >= max_retries {
-                    return Err(e);
-                }
-
-                eprintln!("Attempt {} failed: {}. Retrying in {:?}...", retry_count, e, delay);
-                tokio::time::sleep(delay).await;
-
-                // Exponential backoff: double the delay each time
-                delay *= 2;
-            }
-        }
-    }
-}
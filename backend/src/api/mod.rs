@@ -1,9 +1,40 @@
+pub mod actions;
+pub mod branches;
 pub mod chat;
+pub mod conversations;
 pub mod documents;
+pub mod export;
+pub mod feedback;
+pub mod history;
 pub mod metadata;
+pub mod pdf;
+pub mod presets;
+pub mod search;
+pub mod tools;
+pub mod turns;
 pub mod upload;
+pub mod usage;
 
-pub use chat::{chat_handler, get_chat_history_handler, AppState};
+pub use actions::{list_artifacts_handler, run_action_handler};
+pub use branches::{
+    activate_branch_handler, edit_message_handler, list_branches_handler,
+    regenerate_message_handler, retry_message_handler,
+};
+pub use chat::{chat_handler, chat_stream_handler, get_chat_history_handler, AppState};
+pub use conversations::{
+    create_conversation_handler, delete_conversation_handler, get_conversation_handler,
+    list_conversations_handler, regenerate_title_handler, update_conversation_handler,
+};
 pub use documents::{get_document_handler, list_documents_handler};
+pub use export::export_conversation_handler;
+pub use feedback::{
+    delete_feedback_handler, export_feedback_handler, get_feedback_handler, set_feedback_handler,
+};
 pub use metadata::{backfill_metadata, backfill_metadata_handler};
+pub use presets::{
+    create_preset_handler, delete_preset_handler, get_preset_handler, list_presets_handler,
+    update_preset_handler,
+};
+pub use search::search_messages_handler;
 pub use upload::upload_handler;
+pub use usage::{conversation_usage_handler, daily_usage_handler, document_usage_handler};
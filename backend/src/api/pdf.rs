@@ -0,0 +1,232 @@
+use crate::api::AppState;
+use crate::claude::{ApiErrorResponse, ChatRequest, ChatResponse, ContentBlock, DocumentSource};
+use crate::error::ApiError;
+use std::collections::HashMap;
+use std::future::Future;
+
+/// How to send a document's PDF to Claude: by Files API id when the Files API mode
+/// is on, otherwise inline as base64
+pub async fn load_pdf_source(state: &AppState, document_id: &str) -> Result<DocumentSource, ApiError> {
+    if state.files_policy.enabled {
+        let file_id = ensure_claude_file(state, document_id).await?;
+        return Ok(DocumentSource::File { file_id });
+    }
+
+    let pdf_base64 = load_pdf_base64(state, document_id).await?;
+    Ok(DocumentSource::pdf_base64(pdf_base64))
+}
+
+/// Id of a Files API upload of the document, uploading the PDF if it has none yet.
+///
+/// An upload that has not been checked for `recheck_after` is looked up first, and
+/// uploaded again if it expired or was deleted.
+pub async fn ensure_claude_file(state: &AppState, document_id: &str) -> Result<String, ApiError> {
+    if let Some(file_id) = state.file_ids.get(document_id).await {
+        return Ok(file_id);
+    }
+
+    let stored = state
+        .chat_db
+        .get_document_file_id(document_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    let file_id = match stored {
+        Some(file_id) => {
+            let exists = state
+                .claude
+                .file_exists(&file_id)
+                .await
+                .map_err(|e| ApiError::UpstreamError(format!("Claude API error: {}", e)))?;
+            if !exists {
+                return reupload(state, document_id, &file_id).await;
+            }
+            file_id
+        }
+        None => upload_pdf(state, document_id).await?,
+    };
+
+    remember_file(state, document_id, &file_id).await;
+    Ok(file_id)
+}
+
+/// Run a Claude call that sends a document's PDF, uploading the PDF again and repeating
+/// the call once if Claude no longer has the Files API upload it was sent as
+pub async fn with_pdf_source<T, F, Fut>(state: &AppState, document_id: &str, call: F) -> Result<T, ApiError>
+where
+    F: Fn(DocumentSource) -> Fut,
+    Fut: Future<Output = anyhow::Result<T>>,
+{
+    let source = load_pdf_source(state, document_id).await?;
+    let stale = match &source {
+        DocumentSource::File { file_id } => Some(file_id.clone()),
+        DocumentSource::Base64 { .. } => None,
+    };
+
+    let error = match call(source).await {
+        Ok(result) => return Ok(result),
+        Err(e) => e,
+    };
+    match stale {
+        Some(file_id) if is_missing_file(&error, &file_id) => {
+            state
+                .chat_db
+                .clear_document_file_id(&file_id)
+                .await
+                .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+            let file_id = reupload(state, document_id, &file_id).await?;
+            call(DocumentSource::File { file_id }).await.map_err(upstream_error)
+        }
+        _ => Err(upstream_error(error)),
+    }
+}
+
+/// Send a chat request, uploading PDFs again and resending it once if Claude no longer
+/// has a Files API upload the request points at
+pub async fn chat_with_fresh_files(state: &AppState, request: &mut ChatRequest) -> Result<ChatResponse, ApiError> {
+    let error = match state.claude.chat(request).await {
+        Ok(response) => return Ok(response),
+        Err(e) => e,
+    };
+    if !replace_missing_files(state, request, &error).await? {
+        return Err(upstream_error(error));
+    }
+    state.claude.chat(request).await.map_err(upstream_error)
+}
+
+/// Upload again every PDF the request sends as a Files API upload that `error` reports
+/// missing, and point the request at the new uploads.
+///
+/// Returns false, leaving the request alone, if the error names none of its uploads.
+pub async fn replace_missing_files(
+    state: &AppState,
+    request: &mut ChatRequest,
+    error: &anyhow::Error,
+) -> Result<bool, ApiError> {
+    // Stale upload -> its replacement, for documents sent more than once
+    let mut replaced: HashMap<String, String> = HashMap::new();
+
+    for file_id in file_ids_mut(request) {
+        if let Some(new_id) = replaced.get(file_id.as_str()) {
+            *file_id = new_id.clone();
+            continue;
+        }
+        if !is_missing_file(error, file_id) {
+            continue;
+        }
+
+        let document_id = state
+            .chat_db
+            .clear_document_file_id(file_id)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+        let Some(document_id) = document_id else {
+            continue;
+        };
+        let new_id = reupload(state, &document_id, file_id).await?;
+        replaced.insert(std::mem::replace(file_id, new_id.clone()), new_id);
+    }
+
+    Ok(!replaced.is_empty())
+}
+
+/// Upload a document's PDF again because Claude no longer has its upload `stale_file_id`
+async fn reupload(state: &AppState, document_id: &str, stale_file_id: &str) -> Result<String, ApiError> {
+    println!("File {} for document {} is gone, uploading again", stale_file_id, document_id);
+    let file_id = upload_pdf(state, document_id).await?;
+    remember_file(state, document_id, &file_id).await;
+    Ok(file_id)
+}
+
+/// Cache a live upload, so it is not looked up again for `recheck_after`
+async fn remember_file(state: &AppState, document_id: &str, file_id: &str) {
+    if !state.files_policy.recheck_after.is_zero() {
+        state.file_ids.insert(document_id.to_string(), file_id.to_string()).await;
+    }
+}
+
+/// Whether `error` is Claude reporting that it has no Files API upload `file_id`
+fn is_missing_file(error: &anyhow::Error, file_id: &str) -> bool {
+    error
+        .downcast_ref::<ApiErrorResponse>()
+        .is_some_and(|response| response.is_missing_file(file_id))
+}
+
+/// Files API ids of every document block in the request, including those in tool results
+fn file_ids_mut(request: &mut ChatRequest) -> Vec<&mut String> {
+    fn collect<'a>(blocks: &'a mut [ContentBlock], ids: &mut Vec<&'a mut String>) {
+        for block in blocks {
+            match block {
+                ContentBlock::Document {
+                    source: DocumentSource::File { file_id },
+                    ..
+                } => ids.push(file_id),
+                ContentBlock::ToolResult { content, .. } => collect(content, ids),
+                _ => {}
+            }
+        }
+    }
+
+    let mut ids = Vec::new();
+    for message in &mut request.messages {
+        collect(&mut message.content, &mut ids);
+    }
+    ids
+}
+
+fn upstream_error(error: anyhow::Error) -> ApiError {
+    ApiError::UpstreamError(format!("Claude API error: {}", error))
+}
+
+async fn upload_pdf(state: &AppState, document_id: &str) -> Result<String, ApiError> {
+    let pdf = state
+        .storage
+        .get_pdf(document_id)
+        .await
+        .map_err(|e| ApiError::NotFound(format!("Document not found: {}", e)))?;
+    state
+        .chat_db
+        .ensure_document(document_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    let filename = state
+        .chat_db
+        .get_document(document_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?
+        .map(|d| d.filename)
+        .unwrap_or_else(|| "document.pdf".to_string());
+
+    let file_id = state
+        .claude
+        .upload_file(&filename, pdf)
+        .await
+        .map_err(|e| ApiError::UpstreamError(format!("Claude API error: {}", e)))?;
+
+    state
+        .chat_db
+        .set_document_file_id(document_id, &file_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    println!("Uploaded document {} to the Files API as {}", document_id, file_id);
+    Ok(file_id)
+}
+
+/// Get a PDF from cache or storage
+async fn load_pdf_base64(state: &AppState, document_id: &str) -> Result<String, ApiError> {
+    if let Some(cached) = state.pdf_cache.get(document_id).await {
+        return Ok(cached);
+    }
+
+    // Not in cache, fetch from storage and encode
+    let base64 = state
+        .storage
+        .get_pdf_base64(document_id)
+        .await
+        .map_err(|e| ApiError::NotFound(format!("Document not found: {}", e)))?;
+
+    // Store in cache for future requests
+    state.pdf_cache.insert(document_id.to_string(), base64.clone()).await;
+    Ok(base64)
+}
@@ -0,0 +1,143 @@
+use crate::api::AppState;
+use crate::db::PromptPreset;
+use crate::error::ApiError;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use serde::Deserialize;
+use std::sync::Arc;
+
+const MAX_NAME_CHARS: usize = 100;
+const MAX_SYSTEM_PROMPT_CHARS: usize = 20_000;
+
+#[derive(Debug, Deserialize)]
+pub struct CreatePresetRequest {
+    pub name: String,
+    pub description: Option<String>,
+    /// Role and instructions for Claude; the answer guidelines are always appended
+    pub system_prompt: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdatePresetRequest {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub system_prompt: Option<String>,
+}
+
+/// List all prompt presets by name
+pub async fn list_presets_handler(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<PromptPreset>>, ApiError> {
+    let presets = state
+        .chat_db
+        .list_presets()
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    Ok(Json(presets))
+}
+
+/// Create a prompt preset
+pub async fn create_preset_handler(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<CreatePresetRequest>,
+) -> Result<(StatusCode, Json<PromptPreset>), ApiError> {
+    let name = validate_name(&payload.name)?;
+    let system_prompt = validate_system_prompt(&payload.system_prompt)?;
+
+    let preset_id = state
+        .chat_db
+        .create_preset(name, payload.description.as_deref(), system_prompt)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    let preset = find_preset(&state, &preset_id).await?;
+
+    Ok((StatusCode::CREATED, Json(preset)))
+}
+
+/// Get a prompt preset
+pub async fn get_preset_handler(
+    State(state): State<Arc<AppState>>,
+    Path(preset_id): Path<String>,
+) -> Result<Json<PromptPreset>, ApiError> {
+    let preset = find_preset(&state, &preset_id).await?;
+
+    Ok(Json(preset))
+}
+
+/// Change the name, description or prompt of a preset
+pub async fn update_preset_handler(
+    State(state): State<Arc<AppState>>,
+    Path(preset_id): Path<String>,
+    Json(payload): Json<UpdatePresetRequest>,
+) -> Result<Json<PromptPreset>, ApiError> {
+    find_preset(&state, &preset_id).await?;
+
+    let name = payload.name.as_deref().map(validate_name).transpose()?;
+    let system_prompt = payload
+        .system_prompt
+        .as_deref()
+        .map(validate_system_prompt)
+        .transpose()?;
+
+    state
+        .chat_db
+        .update_preset(&preset_id, name, payload.description.as_deref(), system_prompt)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    let preset = find_preset(&state, &preset_id).await?;
+
+    Ok(Json(preset))
+}
+
+/// Delete a preset; conversations using it go back to the default prompt
+pub async fn delete_preset_handler(
+    State(state): State<Arc<AppState>>,
+    Path(preset_id): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    find_preset(&state, &preset_id).await?;
+
+    state
+        .chat_db
+        .delete_preset(&preset_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn find_preset(state: &AppState, preset_id: &str) -> Result<PromptPreset, ApiError> {
+    state
+        .chat_db
+        .get_preset(preset_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?
+        .ok_or_else(|| ApiError::NotFound(format!("Preset not found: {}", preset_id)))
+}
+
+fn validate_name(name: &str) -> Result<&str, ApiError> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_CHARS {
+        return Err(ApiError::BadRequest(format!(
+            "Name must be between 1 and {} characters",
+            MAX_NAME_CHARS
+        )));
+    }
+    Ok(name)
+}
+
+fn validate_system_prompt(system_prompt: &str) -> Result<&str, ApiError> {
+    let system_prompt = system_prompt.trim();
+    if system_prompt.is_empty() || system_prompt.chars().count() > MAX_SYSTEM_PROMPT_CHARS {
+        return Err(ApiError::BadRequest(format!(
+            "System prompt must be between 1 and {} characters",
+            MAX_SYSTEM_PROMPT_CHARS
+        )));
+    }
+    Ok(system_prompt)
+}
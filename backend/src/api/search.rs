@@ -0,0 +1,104 @@
+use crate::api::AppState;
+use crate::db::MessageSearchHit;
+use crate::error::ApiError;
+use axum::{
+    extract::{Query, State},
+    Json,
+};
+use serde::Deserialize;
+use std::sync::Arc;
+
+const MAX_SEARCH_LIMIT: i64 = 100;
+
+// Private-use characters mark matches in raw snippets, so the snippet text can be
+// escaped before the markers become <mark> tags
+const MATCH_START: &str = "\u{E000}";
+const MATCH_END: &str = "\u{E001}";
+
+#[derive(Debug, Deserialize)]
+pub struct SearchMessagesQuery {
+    pub q: String,
+    /// Only search conversations about this document
+    pub document_id: Option<String>,
+    #[serde(default = "default_limit")]
+    pub limit: i64,
+    #[serde(default)]
+    pub offset: i64,
+}
+
+fn default_limit() -> i64 {
+    20
+}
+
+/// Full-text search over all chat messages, best matches first.
+///
+/// Snippets are HTML-escaped with matched terms wrapped in `<mark>` tags.
+pub async fn search_messages_handler(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<SearchMessagesQuery>,
+) -> Result<Json<Vec<MessageSearchHit>>, ApiError> {
+    if !(1..=MAX_SEARCH_LIMIT).contains(&params.limit) {
+        return Err(ApiError::BadRequest(format!(
+            "limit must be between 1 and {}",
+            MAX_SEARCH_LIMIT
+        )));
+    }
+    if params.offset < 0 {
+        return Err(ApiError::BadRequest("offset must not be negative".to_string()));
+    }
+
+    let query = match_query(&params.q)
+        .ok_or_else(|| ApiError::BadRequest("Search query must not be empty".to_string()))?;
+
+    let hits = state
+        .chat_db
+        .search_messages(
+            &query,
+            params.document_id.as_deref(),
+            (MATCH_START, MATCH_END),
+            params.limit,
+            params.offset,
+        )
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    Ok(Json(
+        hits.into_iter()
+            .map(|hit| MessageSearchHit {
+                snippet: highlight(&hit.snippet),
+                ..hit
+            })
+            .collect(),
+    ))
+}
+
+/// Turn free text into an FTS5 query matching every word, the last one as a prefix.
+///
+/// Each word is quoted so FTS5 operators and punctuation in user input are matched
+/// literally instead of being parsed.
+fn match_query(text: &str) -> Option<String> {
+    let terms: Vec<String> = text
+        .split_whitespace()
+        .map(|term| format!("\"{}\"", term.replace('"', "\"\"")))
+        .collect();
+
+    let last = terms.len().checked_sub(1)?;
+    Some(
+        terms
+            .iter()
+            .enumerate()
+            .map(|(idx, term)| if idx == last { format!("{}*", term) } else { term.clone() })
+            .collect::<Vec<_>>()
+            .join(" "),
+    )
+}
+
+fn highlight(snippet: &str) -> String {
+    snippet
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace(MATCH_START, "<mark>")
+        .replace(MATCH_END, "</mark>")
+}
@@ -0,0 +1,324 @@
+use crate::api::pdf::{chat_with_fresh_files, load_pdf_source};
+use crate::api::documents::DocumentWithMetadata;
+use crate::api::AppState;
+use crate::claude::{
+    CitationsConfig, ChatRequest, ContentBlock, Message, ResponseContent,
+    StopReason, ToolChoice, ToolDefinition, Usage,
+};
+use crate::error::ApiError;
+use crate::models::ToolCall;
+use serde::Deserialize;
+use serde_json::json;
+
+/// Tool-use rounds allowed per answer before Claude must answer without tools
+const MAX_TOOL_ROUNDS: usize = 5;
+/// Extra requests allowed to finish an answer cut off by `max_tokens`
+const MAX_CONTINUATIONS: usize = 2;
+const DEFAULT_SEARCH_LIMIT: i32 = 5;
+const MAX_SEARCH_LIMIT: i32 = 20;
+
+#[derive(Debug, Deserialize)]
+struct SearchLibraryInput {
+    query: String,
+    #[serde(default)]
+    limit: Option<i32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DocumentInput {
+    document_id: String,
+}
+
+/// The final response of a tool-use exchange
+pub struct ToolLoopOutcome {
+    /// Claude's reasoning from the tool-use rounds, followed by the content of the last
+    /// response, which answers the user
+    pub content: Vec<ResponseContent>,
+    /// Usage summed over every round
+    pub usage: Usage,
+    pub tool_calls: Vec<ToolCall>,
+    /// Why the last response stopped; `MaxTokens` means the answer is truncated
+    pub stop_reason: Option<StopReason>,
+}
+
+/// Tools Claude may call while answering
+pub fn tool_definitions() -> Vec<ToolDefinition> {
+    vec![
+        ToolDefinition {
+            name: "search_library".to_string(),
+            description: "Search the user's library of uploaded PDFs by filename, keyword or topic. Returns matching document ids with their filename, keywords and topics.".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "query": { "type": "string", "description": "Text to look for" },
+                    "limit": { "type": "integer", "description": "Maximum number of results (default 5)" }
+                },
+                "required": ["query"]
+            }),
+        },
+        ToolDefinition {
+            name: "get_document_metadata".to_string(),
+            description: "Get the filename, keywords, topics and upload date of a document in the library.".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "document_id": { "type": "string" }
+                },
+                "required": ["document_id"]
+            }),
+        },
+        ToolDefinition {
+            name: "fetch_document".to_string(),
+            description: "Attach another document from the library to the conversation so its contents can be read and cited. Use this when the user refers to another paper in their library.".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "document_id": { "type": "string" }
+                },
+                "required": ["document_id"]
+            }),
+        },
+    ]
+}
+
+/// Send a chat request, running any tools Claude calls and sending their results back
+/// until it answers.
+///
+/// Documents fetched by a tool are appended to `document_ids`, so citation indices keep
+/// matching prompt order, and linked to the conversation for later turns. An answer cut
+/// off by `max_tokens` is continued up to `MAX_CONTINUATIONS` times, except with extended
+/// thinking, which cannot continue a partial answer.
+pub async fn run_with_tools(
+    state: &AppState,
+    conversation_id: &str,
+    mut request: ChatRequest,
+    document_ids: &mut Vec<String>,
+) -> Result<ToolLoopOutcome, ApiError> {
+    request.tools = Some(tool_definitions());
+
+    let mut usage = Usage::default();
+    let mut tool_calls = Vec::new();
+    // Reasoning before each tool call, which is part of how Claude reached the answer, and
+    // any answer text a continued round wrote before calling a tool
+    let mut reasoning: Vec<ResponseContent> = Vec::new();
+    // The answer so far, when it is being continued
+    let mut answer: Vec<ResponseContent> = Vec::new();
+    let mut continuations = 0;
+    let mut round = 0;
+
+    while round <= MAX_TOOL_ROUNDS {
+        if round == MAX_TOOL_ROUNDS {
+            request.tool_choice = Some(ToolChoice::None);
+        }
+
+        let response = chat_with_fresh_files(state, &mut request).await?;
+
+        usage.add(&response.usage);
+
+        let calls: Vec<(String, String, serde_json::Value)> = response
+            .content
+            .iter()
+            .filter_map(|block| match block {
+                ResponseContent::ToolUse { id, name, input } => {
+                    Some((id.clone(), name.clone(), input.clone()))
+                }
+                _ => None,
+            })
+            .collect();
+
+        if calls.is_empty() {
+            let continued = !answer.is_empty();
+            answer.extend(response.content);
+
+            let can_continue = response.stop_reason == Some(StopReason::MaxTokens)
+                && continuations < MAX_CONTINUATIONS
+                && request.thinking.is_none();
+            if !can_continue || !trim_answer_end(&mut answer) {
+                reasoning.extend(answer);
+                return Ok(ToolLoopOutcome {
+                    content: reasoning,
+                    usage,
+                    tool_calls,
+                    stop_reason: response.stop_reason,
+                });
+            }
+
+            // Send the partial answer back as the start of Claude's reply, replacing the
+            // previous one, so it picks up where it stopped
+            if continued {
+                request.messages.pop();
+            }
+            request
+                .messages
+                .push(state.claude.create_assistant_message(&answer));
+            continuations += 1;
+            continue;
+        }
+
+        // A continued answer can go on to call a tool. Claude's turn is then the partial
+        // answer followed by this response, and the answer written so far is kept ahead of
+        // the tool call, so a later continuation starts from a fresh partial.
+        let partial = std::mem::take(&mut answer);
+        let continued = !partial.is_empty();
+        if continued {
+            request.messages.pop();
+        }
+        let mut turn = state.claude.create_assistant_message(&partial);
+        turn.content
+            .extend(state.claude.create_assistant_message(&response.content).content);
+        request.messages.push(turn);
+        reasoning.extend(partial);
+        reasoning.extend(response.content.into_iter().filter(|block| match block {
+            ResponseContent::Thinking { .. } => true,
+            ResponseContent::Text { .. } => continued,
+            _ => false,
+        }));
+
+        let mut results = Vec::with_capacity(calls.len());
+        for (id, name, input) in calls {
+            let (content, output, is_error) =
+                match execute_tool(state, conversation_id, &name, &input, document_ids).await {
+                    Ok((content, output)) => (content, output, false),
+                    Err(message) => (text_content(message.clone()), message, true),
+                };
+
+            results.push(ContentBlock::ToolResult {
+                tool_use_id: id.clone(),
+                content,
+                is_error,
+            });
+            tool_calls.push(ToolCall {
+                id,
+                name,
+                input,
+                output,
+                is_error,
+            });
+        }
+
+        request.messages.push(Message {
+            role: "user".to_string(),
+            content: results,
+        });
+        round += 1;
+    }
+
+    Err(ApiError::UpstreamError(
+        "Claude kept calling tools after they were disabled".to_string(),
+    ))
+}
+
+/// Run a single tool, returning the result blocks for Claude and a text summary to store.
+///
+/// Errors are returned as messages for Claude rather than failing the request.
+async fn execute_tool(
+    state: &AppState,
+    conversation_id: &str,
+    name: &str,
+    input: &serde_json::Value,
+    document_ids: &mut Vec<String>,
+) -> Result<(Vec<ContentBlock>, String), String> {
+    match name {
+        "search_library" => {
+            let input: SearchLibraryInput = parse_input(input)?;
+            let limit = input
+                .limit
+                .unwrap_or(DEFAULT_SEARCH_LIMIT)
+                .clamp(1, MAX_SEARCH_LIMIT);
+
+            let documents = state
+                .chat_db
+                .search_documents(input.query.trim(), limit)
+                .await
+                .map_err(|e| format!("Search failed: {}", e))?;
+            let documents: Vec<DocumentWithMetadata> =
+                documents.into_iter().map(Into::into).collect();
+
+            let output = serde_json::to_string(&documents).map_err(|e| e.to_string())?;
+            Ok((text_content(output.clone()), output))
+        }
+        "get_document_metadata" => {
+            let input: DocumentInput = parse_input(input)?;
+
+            let document = state
+                .chat_db
+                .get_document(&input.document_id)
+                .await
+                .map_err(|e| format!("Lookup failed: {}", e))?
+                .ok_or_else(|| format!("Document not found: {}", input.document_id))?;
+
+            let output = serde_json::to_string(&DocumentWithMetadata::from(document))
+                .map_err(|e| e.to_string())?;
+            Ok((text_content(output.clone()), output))
+        }
+        "fetch_document" => {
+            let input: DocumentInput = parse_input(input)?;
+
+            if document_ids.contains(&input.document_id) {
+                let output = "This document is already attached to the conversation.".to_string();
+                return Ok((text_content(output.clone()), output));
+            }
+
+            let document = state
+                .chat_db
+                .get_document(&input.document_id)
+                .await
+                .map_err(|e| format!("Lookup failed: {}", e))?
+                .ok_or_else(|| format!("Document not found: {}", input.document_id))?;
+            let source = load_pdf_source(state, &document.id)
+                .await
+                .map_err(|e| e.to_string())?;
+
+            state
+                .chat_db
+                .add_conversation_documents(conversation_id, std::slice::from_ref(&document.id))
+                .await
+                .map_err(|e| format!("Failed to attach document: {}", e))?;
+            document_ids.push(document.id);
+
+            let output = format!("Attached {}", document.filename);
+            let content = vec![
+                ContentBlock::Document {
+                    source,
+                    title: Some(document.filename),
+                    citations: Some(CitationsConfig { enabled: true }),
+                    cache_control: None,
+                },
+                ContentBlock::Text {
+                    text: output.clone(),
+                    cache_control: None,
+                },
+            ];
+            Ok((content, output))
+        }
+        _ => Err(format!("Unknown tool: {}", name)),
+    }
+}
+
+/// Trim trailing whitespace from a partial answer, which the API rejects at the end of
+/// the final assistant message. Returns false if the answer has no text to continue
+fn trim_answer_end(answer: &mut [ResponseContent]) -> bool {
+    let last_text = answer.iter_mut().rev().find_map(|block| match block {
+        ResponseContent::Text { text, .. } if !text.trim().is_empty() => Some(text),
+        _ => None,
+    });
+
+    match last_text {
+        Some(text) => {
+            text.truncate(text.trim_end().len());
+            true
+        }
+        None => false,
+    }
+}
+
+fn parse_input<T: serde::de::DeserializeOwned>(input: &serde_json::Value) -> Result<T, String> {
+    serde_json::from_value(input.clone()).map_err(|e| format!("Invalid tool input: {}", e))
+}
+
+fn text_content(text: String) -> Vec<ContentBlock> {
+    vec![ContentBlock::Text {
+        text,
+        cache_control: None,
+    }]
+}
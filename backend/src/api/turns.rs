@@ -0,0 +1,292 @@
+use crate::api::chat::fail_exchange;
+use crate::api::AppState;
+use crate::error::ApiError;
+use crate::models::{ChatApiRequest, ChatApiResponse, TurnStatus};
+use axum::http::HeaderMap;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+const MAX_IDEMPOTENCY_KEY_CHARS: usize = 255;
+const CANCELLED_ERROR: &str = "Cancelled because the client disconnected";
+
+/// How a chat request should proceed
+pub enum TurnStart {
+    /// The request repeats one that already completed; answer with its response
+    Replay(ChatApiResponse),
+    Started(TurnGuard),
+}
+
+/// A pending chat turn.
+///
+/// Axum drops the handler future when the client disconnects, which aborts the
+/// in-flight Claude request. If the guard is dropped before `complete` or `fail` is
+/// called, the turn is recorded as cancelled and its pending reply as failed.
+pub struct TurnGuard {
+    state: Arc<AppState>,
+    turn_id: String,
+    conversation_id: Option<String>,
+    /// The pending assistant message the turn fills in
+    message_id: Option<String>,
+    /// The turn retries an earlier attempt and fills in the reply that attempt left failed
+    resumed: bool,
+    finished: bool,
+}
+
+impl TurnGuard {
+    /// The conversation and reply left by an earlier attempt of this turn, now pending
+    /// again, if the turn picks up where that attempt stopped
+    pub fn resumed_reply(&self) -> Option<(&str, &str)> {
+        if !self.resumed {
+            return None;
+        }
+        self.conversation_id.as_deref().zip(self.message_id.as_deref())
+    }
+
+    /// Note the conversation and pending reply of the turn once they are stored
+    pub async fn set_reply(&mut self, conversation_id: &str, message_id: &str) {
+        self.conversation_id = Some(conversation_id.to_string());
+        self.message_id = Some(message_id.to_string());
+
+        if let Err(e) = self
+            .state
+            .chat_db
+            .set_chat_turn_reply(&self.turn_id, conversation_id, message_id)
+            .await
+        {
+            eprintln!("Failed to record the reply of chat turn {}: {}", self.turn_id, e);
+        }
+    }
+
+    pub async fn complete(mut self, response: &ChatApiResponse) {
+        self.finished = true;
+        self.finish(TurnStatus::Completed, Some(response), None)
+            .await;
+    }
+
+    /// Record the turn, and its pending reply if one was stored, as failed
+    pub async fn fail(mut self, error: &str) {
+        self.finished = true;
+        if let Some(message_id) = &self.message_id {
+            fail_exchange(&self.state, message_id, error).await;
+        }
+        self.finish(TurnStatus::Failed, None, Some(error)).await;
+    }
+
+    async fn finish(
+        &self,
+        status: TurnStatus,
+        response: Option<&ChatApiResponse>,
+        error: Option<&str>,
+    ) {
+        if let Err(e) = self
+            .state
+            .chat_db
+            .finish_chat_turn(
+                &self.turn_id,
+                status,
+                self.conversation_id.as_deref(),
+                response,
+                error,
+            )
+            .await
+        {
+            eprintln!("Failed to record chat turn {}: {}", self.turn_id, e);
+        }
+    }
+}
+
+impl Drop for TurnGuard {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+
+        let state = self.state.clone();
+        let turn_id = std::mem::take(&mut self.turn_id);
+        let conversation_id = self.conversation_id.take();
+        let message_id = self.message_id.take();
+        tokio::spawn(async move {
+            if let Some(message_id) = message_id {
+                fail_exchange(&state, &message_id, CANCELLED_ERROR).await;
+            }
+            if let Err(e) = state
+                .chat_db
+                .finish_chat_turn(
+                    &turn_id,
+                    TurnStatus::Cancelled,
+                    conversation_id.as_deref(),
+                    None,
+                    None,
+                )
+                .await
+            {
+                eprintln!("Failed to record cancelled chat turn {}: {}", turn_id, e);
+            }
+        });
+    }
+}
+
+/// A pending assistant message being answered outside a chat turn, e.g. a regenerated
+/// reply.
+///
+/// Like [`TurnGuard`], it marks the message failed if it is dropped before `finish` is
+/// called because the client disconnected, so the reply can be retried.
+pub struct ReplyGuard {
+    state: Arc<AppState>,
+    message_id: Option<String>,
+}
+
+impl ReplyGuard {
+    pub fn new(state: &Arc<AppState>, message_id: &str) -> Self {
+        Self {
+            state: state.clone(),
+            message_id: Some(message_id.to_string()),
+        }
+    }
+
+    pub fn message_id(&self) -> &str {
+        self.message_id.as_deref().unwrap_or_default()
+    }
+
+    /// The message was answered or recorded as failed; nothing is left to clean up
+    pub fn finish(mut self) {
+        self.message_id = None;
+    }
+}
+
+impl Drop for ReplyGuard {
+    fn drop(&mut self) {
+        let Some(message_id) = self.message_id.take() else {
+            return;
+        };
+
+        let state = self.state.clone();
+        tokio::spawn(async move {
+            fail_exchange(&state, &message_id, CANCELLED_ERROR).await;
+        });
+    }
+}
+
+/// Record a new chat turn, or find the stored response if the request carries an
+/// `Idempotency-Key` that already completed.
+///
+/// A key whose earlier request failed or was cancelled may be retried, filling in the
+/// reply that request left failed instead of asking the question again. A key whose
+/// request is still running, or that was used for a different request, is rejected.
+pub async fn begin_turn(
+    state: &Arc<AppState>,
+    headers: &HeaderMap,
+    payload: &ChatApiRequest,
+) -> Result<TurnStart, ApiError> {
+    let idempotency_key = idempotency_key(headers)?;
+    let request_hash = request_hash(payload)?;
+
+    let created = state
+        .chat_db
+        .create_chat_turn(
+            idempotency_key.as_deref(),
+            &request_hash,
+            &payload.document_id,
+            payload.conversation_id.as_deref(),
+        )
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    let (turn_id, resumed) = match (created, idempotency_key) {
+        (Some(turn_id), _) => (turn_id, None),
+        (None, Some(key)) => {
+            let existing = state
+                .chat_db
+                .get_chat_turn_by_key(&key)
+                .await
+                .map_err(|e| ApiError::DatabaseError(e.to_string()))?
+                .ok_or_else(|| ApiError::InternalError("Idempotency key vanished".to_string()))?;
+
+            if existing.request_hash != request_hash {
+                return Err(ApiError::BadRequest(
+                    "Idempotency-Key was already used for a different request".to_string(),
+                ));
+            }
+
+            match existing.status {
+                TurnStatus::Completed => {
+                    let response = existing.response.ok_or_else(|| {
+                        ApiError::InternalError("Completed chat turn has no response".to_string())
+                    })?;
+                    return Ok(TurnStart::Replay(response.0));
+                }
+                TurnStatus::Pending => return Err(in_progress()),
+                TurnStatus::Failed | TurnStatus::Cancelled => {
+                    let restarted = state
+                        .chat_db
+                        .restart_chat_turn(&existing.id)
+                        .await
+                        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+                    if !restarted {
+                        return Err(in_progress());
+                    }
+
+                    // Fill in the reply the earlier attempt left failed, unless it was
+                    // retried through the retry endpoint since
+                    let resumed = match (existing.conversation_id, existing.message_id) {
+                        (Some(conversation_id), Some(message_id)) => state
+                            .chat_db
+                            .restart_message(&message_id)
+                            .await
+                            .map_err(|e| ApiError::DatabaseError(e.to_string()))?
+                            .then_some((conversation_id, message_id)),
+                        _ => None,
+                    };
+                    (existing.id, resumed)
+                }
+            }
+        }
+        (None, None) => {
+            return Err(ApiError::InternalError(
+                "Failed to record chat turn".to_string(),
+            ))
+        }
+    };
+
+    let (conversation_id, message_id) = match resumed {
+        Some((conversation_id, message_id)) => (Some(conversation_id), Some(message_id)),
+        None => (payload.conversation_id.clone(), None),
+    };
+    Ok(TurnStart::Started(TurnGuard {
+        state: state.clone(),
+        turn_id,
+        resumed: message_id.is_some(),
+        conversation_id,
+        message_id,
+        finished: false,
+    }))
+}
+
+fn in_progress() -> ApiError {
+    ApiError::Conflict("A request with this Idempotency-Key is still in progress".to_string())
+}
+
+fn idempotency_key(headers: &HeaderMap) -> Result<Option<String>, ApiError> {
+    let Some(value) = headers.get(IDEMPOTENCY_KEY_HEADER) else {
+        return Ok(None);
+    };
+
+    let key = value
+        .to_str()
+        .map_err(|_| ApiError::BadRequest("Idempotency-Key must be ASCII".to_string()))?
+        .trim();
+    if key.is_empty() || key.len() > MAX_IDEMPOTENCY_KEY_CHARS {
+        return Err(ApiError::BadRequest(format!(
+            "Idempotency-Key must be between 1 and {} characters",
+            MAX_IDEMPOTENCY_KEY_CHARS
+        )));
+    }
+
+    Ok(Some(key.to_string()))
+}
+
+fn request_hash(payload: &ChatApiRequest) -> Result<String, ApiError> {
+    let body = serde_json::to_vec(payload).map_err(|e| ApiError::InternalError(e.to_string()))?;
+    Ok(format!("{:x}", Sha256::digest(&body)))
+}
@@ -46,7 +46,8 @@ pub async fn upload_handler(
                 .await
                 .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
 
-            // Extract metadata in background (don't block upload response)
+            // Extract metadata in background (don't block upload response). In Files API
+            // mode this is also where the PDF is uploaded to Claude.
             let state_clone = state.clone();
             let doc_id = document_id.clone();
             tokio::spawn(async move {
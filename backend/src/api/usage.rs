@@ -0,0 +1,151 @@
+use crate::api::AppState;
+use crate::claude::Usage;
+use crate::db::{NewUsageRecord, UsageSummary};
+use crate::error::ApiError;
+use axum::{
+    extract::{Path, Query, State},
+    Json,
+};
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+#[derive(Debug, Deserialize)]
+pub struct DailyUsageQuery {
+    #[serde(default = "default_days")]
+    days: i64,
+}
+
+fn default_days() -> i64 {
+    30
+}
+
+#[derive(Debug, Serialize)]
+pub struct UsageReport {
+    #[serde(flatten)]
+    pub summary: UsageSummary,
+    /// Share of prompt tokens served from the cache
+    pub cache_hit_ratio: f64,
+    /// Difference between the uncached and actual cost
+    pub cache_savings_usd: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DailyUsageReport {
+    pub day: String,
+    #[serde(flatten)]
+    pub report: UsageReport,
+}
+
+impl From<UsageSummary> for UsageReport {
+    fn from(summary: UsageSummary) -> Self {
+        let prompt_tokens = summary.input_tokens
+            + summary.cache_creation_input_tokens
+            + summary.cache_read_input_tokens;
+        let cache_hit_ratio = if prompt_tokens > 0 {
+            summary.cache_read_input_tokens as f64 / prompt_tokens as f64
+        } else {
+            0.0
+        };
+
+        Self {
+            cache_hit_ratio,
+            cache_savings_usd: summary.uncached_cost_usd - summary.cost_usd,
+            summary,
+        }
+    }
+}
+
+/// Identifies what a Claude call was made for when recording its usage
+#[derive(Debug, Default)]
+pub struct UsageContext<'a> {
+    pub kind: &'a str,
+    pub conversation_id: Option<&'a str>,
+    pub document_id: Option<&'a str>,
+    pub message_id: Option<&'a str>,
+}
+
+/// Record the usage and cost of a Claude call.
+///
+/// Failures are logged rather than returned, so accounting never breaks a request.
+pub async fn record_usage(state: &AppState, context: UsageContext<'_>, model: &str, usage: &Usage) {
+    let cost = state.pricing.cost(model, usage);
+
+    let result = state
+        .chat_db
+        .record_usage(NewUsageRecord {
+            kind: context.kind,
+            conversation_id: context.conversation_id,
+            document_id: context.document_id,
+            message_id: context.message_id,
+            model,
+            input_tokens: usage.input_tokens,
+            output_tokens: usage.output_tokens,
+            cache_creation_input_tokens: usage.cache_creation_input_tokens.unwrap_or(0),
+            cache_read_input_tokens: usage.cache_read_input_tokens.unwrap_or(0),
+            cost_usd: cost.cost_usd,
+            uncached_cost_usd: cost.uncached_cost_usd,
+        })
+        .await;
+
+    if let Err(e) = result {
+        eprintln!("Failed to record {} usage: {}", context.kind, e);
+    }
+}
+
+/// Total spend and cache efficiency for a document, across chat and metadata calls
+pub async fn document_usage_handler(
+    State(state): State<Arc<AppState>>,
+    Path(document_id): Path<String>,
+) -> Result<Json<UsageReport>, ApiError> {
+    let summary = state
+        .chat_db
+        .document_usage(&document_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    Ok(Json(summary.into()))
+}
+
+/// Total spend and cache efficiency for a conversation
+pub async fn conversation_usage_handler(
+    State(state): State<Arc<AppState>>,
+    Path(conversation_id): Path<String>,
+) -> Result<Json<UsageReport>, ApiError> {
+    let summary = state
+        .chat_db
+        .conversation_usage(&conversation_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    Ok(Json(summary.into()))
+}
+
+/// Spend and cache efficiency per day over the last `days` days
+pub async fn daily_usage_handler(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<DailyUsageQuery>,
+) -> Result<Json<Vec<DailyUsageReport>>, ApiError> {
+    if !(1..=366).contains(&params.days) {
+        return Err(ApiError::BadRequest("days must be between 1 and 366".to_string()));
+    }
+
+    let since = (Utc::now() - Duration::days(params.days - 1))
+        .format("%Y-%m-%d")
+        .to_string();
+
+    let days = state
+        .chat_db
+        .daily_usage(&since)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    Ok(Json(
+        days.into_iter()
+            .map(|d| DailyUsageReport {
+                day: d.day,
+                report: d.summary.into(),
+            })
+            .collect(),
+    ))
+}
@@ -1,68 +1,202 @@
+use super::retry::{backoff_delay, classify, RetryDecision};
 use super::types::*;
+use crate::config::{ClaudeEndpoint, RetryPolicy};
 use anyhow::Result;
-use reqwest::Client;
+use futures_util::{Stream, StreamExt};
+use bytes::Bytes;
+use reqwest::multipart::{Form, Part};
+use reqwest::{Client, Method, RequestBuilder, StatusCode};
 
 pub struct ClaudeClient {
     client: Client,
     api_key: String,
     model: String,
+    endpoint: ClaudeEndpoint,
+    retry: RetryPolicy,
 }
 
 impl ClaudeClient {
-    pub fn new(api_key: String) -> Self {
+    /// Create a client; `model` is used for internal calls such as metadata extraction
+    pub fn new(api_key: String, model: String, endpoint: ClaudeEndpoint, retry: RetryPolicy) -> Self {
         Self {
             client: Client::new(),
             api_key,
-            model: "claude-sonnet-4-5-20250929".to_string(),
+            model,
+            endpoint,
+            retry,
         }
     }
 
-    pub async fn chat(&self, request: ChatRequest) -> Result<ChatResponse> {
+    pub async fn chat(&self, request: &ChatRequest) -> Result<ChatResponse> {
+        let response = self.send(request).await?;
+        let body = response.text().await?;
+
+        serde_json::from_str(&body)
+            .map_err(|e| anyhow::anyhow!("Failed to parse Claude response: {}. Response was: {}", e, body))
+    }
+
+    /// Send a streaming request and yield the parsed server-sent events
+    pub async fn chat_stream(
+        &self,
+        request: &ChatRequest,
+    ) -> Result<impl Stream<Item = Result<StreamEvent>> + Send> {
+        let response = self.send(&StreamingRequest { request, stream: true }).await?;
+
+        let events = futures_util::stream::unfold(
+            (response.bytes_stream(), Vec::new()),
+            |(mut body, mut buffer)| async move {
+                loop {
+                    // Events are separated by a blank line
+                    if let Some(end) = sse_frame_end(&buffer) {
+                        let frame: Vec<u8> = buffer.drain(..end).collect();
+                        if let Some(event) = parse_sse_frame(&frame) {
+                            return Some((event, (body, buffer)));
+                        }
+                        continue;
+                    }
+
+                    match body.next().await {
+                        Some(Ok(chunk)) => buffer.extend_from_slice(&chunk),
+                        Some(Err(e)) => return Some((Err(e.into()), (body, buffer))),
+                        None => return None,
+                    }
+                }
+            },
+        );
+
+        Ok(events)
+    }
+
+    async fn send(&self, request: &impl serde::Serialize) -> Result<reqwest::Response> {
         let response = self
-            .client
-            .post("https://api.anthropic.com/v1/messages")
-            .header("x-api-key", &self.api_key)
-            .header("anthropic-version", "2023-06-01")
-            .header("anthropic-beta", "prompt-caching-2024-07-31")
-            .header("content-type", "application/json")
-            .json(&request)
-            .send()
+            .execute(|| Ok(self.request(Method::POST, "/v1/messages").json(request)))
+            .await?;
+        check_status(response).await
+    }
+
+    /// Upload a PDF to the Files API and return its file id
+    pub async fn upload_file(&self, filename: &str, data: Bytes) -> Result<String> {
+        let response = self
+            .execute(|| {
+                let part = Part::stream(data.clone())
+                    .file_name(filename.to_string())
+                    .mime_str("application/pdf")?;
+                Ok(self
+                    .request(Method::POST, "/v1/files")
+                    .multipart(Form::new().part("file", part)))
+            })
             .await?;
+        let body = check_status(response).await?.text().await?;
+
+        let file: FileMetadata = serde_json::from_str(&body)
+            .map_err(|e| anyhow::anyhow!("Failed to parse Files API response: {}. Response was: {}", e, body))?;
+        Ok(file.id)
+    }
 
-        if !response.status().is_success() {
-            let error_text = response.text().await?;
-            anyhow::bail!("Claude API error: {}", error_text);
+    /// Whether a file uploaded through the Files API is still there
+    pub async fn file_exists(&self, file_id: &str) -> Result<bool> {
+        let response = self
+            .execute(|| Ok(self.request(Method::GET, &format!("/v1/files/{}", file_id))))
+            .await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(false);
         }
 
-        Ok(response.json().await?)
+        check_status(response).await?;
+        Ok(true)
+    }
+
+    /// Send a request, retrying rate limits, overload, server errors and failed
+    /// connections as the retry policy allows.
+    ///
+    /// Returns the last response, which is an error response when the failure was not
+    /// worth retrying or the retries ran out.
+    async fn execute(&self, build: impl Fn() -> Result<RequestBuilder>) -> Result<reqwest::Response> {
+        let mut retries = 0;
+
+        loop {
+            let (failure, delay) = match build()?.send().await {
+                Ok(response) if response.status().is_success() => return Ok(response),
+                Ok(response) => {
+                    let delay = match classify(response.status(), response.headers()) {
+                        RetryDecision::Never => return Ok(response),
+                        RetryDecision::Backoff => backoff_delay(&self.retry, retries),
+                        RetryDecision::After(wait) => wait,
+                    };
+                    if retries >= self.retry.max_retries || delay > self.retry.max_delay {
+                        return Ok(response);
+                    }
+                    (format!("Claude API returned {}", response.status()), delay)
+                }
+                // Only a failed connection guarantees the request was never received; a
+                // timeout or broken response may come after Claude already acted on it
+                Err(e) => {
+                    if !e.is_connect() || retries >= self.retry.max_retries {
+                        return Err(e.into());
+                    }
+                    (format!("Claude request failed: {}", e), backoff_delay(&self.retry, retries))
+                }
+            };
+
+            retries += 1;
+            eprintln!(
+                "{}. Retry {} of {} in {:?}...",
+                failure, retries, self.retry.max_retries, delay
+            );
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        let mut builder = self
+            .client
+            .request(method, self.endpoint.url(path))
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", &self.endpoint.api_version);
+        if !self.endpoint.betas.is_empty() {
+            builder = builder.header("anthropic-beta", self.endpoint.betas.join(","));
+        }
+        builder
     }
 
     /// Create a message with PDF document (with cache control for first message)
-    pub fn create_pdf_message(&self, pdf_base64: String, text: String, enable_cache: bool) -> Message {
-        let cache_control = if enable_cache {
-            Some(super::types::CacheControl {
-                cache_type: "ephemeral".to_string(),
+    pub fn create_pdf_message(&self, source: DocumentSource, text: String, enable_cache: bool) -> Message {
+        self.create_documents_message(vec![(None, source)], text, enable_cache, false)
+    }
+
+    /// Create a message with one document block per PDF, given as (title, source) pairs.
+    ///
+    /// Only the last document carries cache control, so a single breakpoint covers all of them.
+    pub fn create_documents_message(
+        &self,
+        documents: Vec<(Option<String>, DocumentSource)>,
+        text: String,
+        enable_cache: bool,
+        enable_citations: bool,
+    ) -> Message {
+        let last = documents.len().saturating_sub(1);
+
+        let mut content: Vec<ContentBlock> = documents
+            .into_iter()
+            .enumerate()
+            .map(|(idx, (title, source))| ContentBlock::Document {
+                source,
+                title,
+                citations: enable_citations.then_some(CitationsConfig { enabled: true }),
+                cache_control: (enable_cache && idx == last).then(|| super::types::CacheControl {
+                    cache_type: "ephemeral".to_string(),
+                }),
             })
-        } else {
-            None
-        };
+            .collect();
+
+        content.push(ContentBlock::Text {
+            text,
+            cache_control: None,
+        });
 
         Message {
             role: "user".to_string(),
-            content: vec![
-                ContentBlock::Document {
-                    source: DocumentSource {
-                        source_type: "base64".to_string(),
-                        media_type: "application/pdf".to_string(),
-                        data: pdf_base64,
-                    },
-                    cache_control: cache_control.clone(),
-                },
-                ContentBlock::Text {
-                    text,
-                    cache_control: None,
-                },
-            ],
+            content,
         }
     }
 
@@ -77,10 +211,58 @@ impl ClaudeClient {
         }
     }
 
+    /// Echo an assistant response back as a message, so tool results or a continuation
+    /// can follow it
+    pub fn create_assistant_message(&self, content: &[ResponseContent]) -> Message {
+        // The API rejects empty text blocks, which responses may contain
+        let content = content
+            .iter()
+            .filter_map(|block| match block {
+                ResponseContent::Text { text, .. } if text.is_empty() => None,
+                ResponseContent::Text { text, .. } => Some(ContentBlock::Text {
+                    text: text.clone(),
+                    cache_control: None,
+                }),
+                ResponseContent::ToolUse { id, name, input } => Some(ContentBlock::ToolUse {
+                    id: id.clone(),
+                    name: name.clone(),
+                    input: input.clone(),
+                }),
+                // Thinking blocks must be sent back as-is for Claude to continue after a
+                // tool call
+                ResponseContent::Thinking {
+                    thinking,
+                    signature,
+                } => Some(ContentBlock::Thinking {
+                    thinking: thinking.clone(),
+                    signature: signature.clone(),
+                }),
+                ResponseContent::RedactedThinking { data } => {
+                    Some(ContentBlock::RedactedThinking { data: data.clone() })
+                }
+                // Server tool blocks and unknown blocks go back exactly as they came
+                block => serde_json::to_value(block).ok().map(ContentBlock::Raw),
+            })
+            .collect();
+
+        Message {
+            role: "assistant".to_string(),
+            content,
+        }
+    }
+
+    /// Model used for internal calls such as metadata extraction
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
     /// Extract keywords and topics from a PDF document
-    pub async fn extract_metadata(&self, pdf_base64: String) -> Result<super::types::MetadataExtractionResponse> {
+    pub async fn extract_metadata(
+        &self,
+        source: DocumentSource,
+    ) -> Result<(super::types::MetadataExtractionResponse, Usage)> {
         let message = self.create_pdf_message(
-            pdf_base64,
+            source,
             "Extract keywords and topics from this PDF document. Analyze the content and return ONLY a valid JSON object with this exact format: {\"keywords\": [\"keyword1\", \"keyword2\", ...], \"topics\": [\"topic1\", \"topic2\", ...]}. Provide 5-10 relevant keywords and 3-5 main topics. No additional text, just the JSON.".to_string(),
             false,
         );
@@ -90,12 +272,13 @@ impl ClaudeClient {
             max_tokens: 1024,
             messages: vec![message],
             system: None,
+            ..Default::default()
         };
 
-        let response = self.chat(request).await?;
+        let response = self.chat(&request).await?;
 
         // Extract text from response
-        if let Some(super::types::ResponseContent::Text { text }) = response.content.first() {
+        if let Some(super::types::ResponseContent::Text { text, .. }) = response.content.first() {
             // Try to parse JSON, handling possible markdown code blocks
             let json_text = text.trim()
                 .trim_start_matches("```json")
@@ -106,9 +289,235 @@ impl ClaudeClient {
             let metadata: super::types::MetadataExtractionResponse = serde_json::from_str(json_text)
                 .map_err(|e| anyhow::anyhow!("Failed to parse metadata JSON: {}. Response was: {}", e, text))?;
 
-            Ok(metadata)
+            Ok((metadata, response.usage))
+        } else {
+            anyhow::bail!("No text content in response")
+        }
+    }
+
+    /// Run a one-off analysis of a PDF, such as a summary or a critique
+    pub async fn analyze_document(
+        &self,
+        source: DocumentSource,
+        instructions: &str,
+        max_tokens: u32,
+    ) -> Result<(String, Usage)> {
+        let message = self.create_pdf_message(source, instructions.to_string(), true);
+
+        let request = ChatRequest {
+            model: self.model.clone(),
+            max_tokens,
+            messages: vec![message],
+            ..Default::default()
+        };
+
+        let response = self.chat(&request).await?;
+
+        let text: String = response
+            .content
+            .iter()
+            .filter_map(|block| match block {
+                ResponseContent::Text { text, .. } => Some(text.as_str()),
+                _ => None,
+            })
+            .collect();
+        if text.trim().is_empty() {
+            anyhow::bail!("No text content in response");
+        }
+
+        Ok((text.trim().to_string(), response.usage))
+    }
+
+    /// Generate a short conversation title from the first exchange
+    pub async fn generate_title(&self, question: &str, answer: &str) -> Result<(String, Usage)> {
+        let prompt = format!(
+            "Write a short title (at most 6 words) for a conversation that starts with the exchange below. Return ONLY the title, with no quotes or punctuation at the end.\n\nUser: {}\n\nAssistant: {}",
+            question, answer
+        );
+
+        let request = ChatRequest {
+            model: self.model.clone(),
+            max_tokens: 32,
+            messages: vec![self.create_text_message("user", prompt)],
+            system: None,
+            ..Default::default()
+        };
+
+        let response = self.chat(&request).await?;
+
+        if let Some(ResponseContent::Text { text, .. }) = response.content.first() {
+            let title = text.trim().trim_matches('"').trim_end_matches('.').trim();
+            if title.is_empty() {
+                anyhow::bail!("Empty title in response");
+            }
+            Ok((title.chars().take(80).collect(), response.usage))
+        } else {
+            anyhow::bail!("No text content in response")
+        }
+    }
+
+    /// Fold older conversation turns into a running summary
+    pub async fn summarize_conversation(
+        &self,
+        previous_summary: Option<&str>,
+        transcript: &str,
+    ) -> Result<(String, Usage)> {
+        let previous = previous_summary
+            .map(|s| format!("Summary of the conversation so far:\n{}\n\n", s))
+            .unwrap_or_default();
+        let prompt = format!(
+            "{}Update the summary with the following turns of a conversation about one or more documents. Keep every question the user asked, the key facts and page references from the answers, and any open threads. Return ONLY the summary.\n\n{}",
+            previous, transcript
+        );
+
+        let request = ChatRequest {
+            model: self.model.clone(),
+            max_tokens: 1024,
+            messages: vec![self.create_text_message("user", prompt)],
+            system: None,
+            ..Default::default()
+        };
+
+        let response = self.chat(&request).await?;
+
+        if let Some(ResponseContent::Text { text, .. }) = response.content.first() {
+            Ok((text.trim().to_string(), response.usage))
         } else {
             anyhow::bail!("No text content in response")
         }
     }
 }
+
+/// End of the first complete event in `buffer`, including the blank line after it.
+///
+/// Lines may end in `\n` or `\r\n`, so the blank line is either `\n\n` or `\r\n\r\n`.
+fn sse_frame_end(buffer: &[u8]) -> Option<usize> {
+    let lf = buffer.windows(2).position(|w| w == b"\n\n").map(|pos| pos + 2);
+    let crlf = buffer
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .map(|pos| pos + 4);
+
+    match (lf, crlf) {
+        (Some(lf), Some(crlf)) => Some(lf.min(crlf)),
+        (lf, crlf) => lf.or(crlf),
+    }
+}
+
+/// Parse a single SSE frame, returning `None` for frames without a data payload
+fn parse_sse_frame(frame: &[u8]) -> Option<Result<StreamEvent>> {
+    let frame = String::from_utf8_lossy(frame);
+    let data = frame
+        .lines()
+        .filter_map(|line| line.strip_prefix("data:"))
+        .map(str::trim_start)
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    if data.is_empty() {
+        return None;
+    }
+
+    Some(
+        serde_json::from_str(&data)
+            .map_err(|e| anyhow::anyhow!("Failed to parse stream event: {}. Data was: {}", e, data)),
+    )
+}
+
+/// An error response from the Claude API
+#[derive(Debug)]
+pub struct ApiErrorResponse {
+    pub status: StatusCode,
+    /// `error.type` from the body, e.g. `not_found_error`, if the body was a JSON error
+    pub error_type: Option<String>,
+    /// `error.message` from the body, or the whole body if it was not a JSON error
+    pub message: String,
+}
+
+impl ApiErrorResponse {
+    fn parse(status: StatusCode, body: &str) -> Self {
+        match serde_json::from_str::<ErrorResponse>(body) {
+            Ok(ErrorResponse { error }) => Self {
+                status,
+                error_type: Some(error.error_type),
+                message: error.message,
+            },
+            Err(_) => Self {
+                status,
+                error_type: None,
+                message: body.to_string(),
+            },
+        }
+    }
+
+    /// Whether Claude reports that it has no Files API upload `file_id`, e.g. because
+    /// it expired or was deleted
+    pub fn is_missing_file(&self, file_id: &str) -> bool {
+        let not_found = self.status == StatusCode::NOT_FOUND
+            || self.error_type.as_deref() == Some("not_found_error");
+        not_found && self.message.contains(file_id)
+    }
+}
+
+impl std::fmt::Display for ApiErrorResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.error_type {
+            Some(error_type) => write!(
+                f,
+                "Claude API error ({}): {}: {}",
+                self.status, error_type, self.message
+            ),
+            None => write!(f, "Claude API error ({}): {}", self.status, self.message),
+        }
+    }
+}
+
+impl std::error::Error for ApiErrorResponse {}
+
+/// Turn an error response into an [`ApiErrorResponse`] carrying its status and error
+async fn check_status(response: reqwest::Response) -> Result<reqwest::Response> {
+    if response.status().is_success() {
+        return Ok(response);
+    }
+
+    let status = response.status();
+    let body = response.text().await.unwrap_or_default();
+    Err(ApiErrorResponse::parse(status, &body).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_end_at_a_blank_line_with_either_line_ending() {
+        assert_eq!(sse_frame_end(b"data: {}\n\nevent: ping"), Some(10));
+        assert_eq!(sse_frame_end(b"data: {}\r\n\r\nevent: ping"), Some(12));
+        assert_eq!(sse_frame_end(b"data: {}\r\n\r\ndata: {}\n\n"), Some(12));
+        assert_eq!(sse_frame_end(b"data: {}\r\n"), None);
+    }
+
+    #[test]
+    fn parses_a_crlf_frame() {
+        let frame = b"event: message_stop\r\ndata: {\"type\":\"message_stop\"}\r\n\r\n";
+        let event = parse_sse_frame(frame).unwrap().unwrap();
+        assert!(matches!(event, StreamEvent::MessageStop));
+    }
+
+    #[test]
+    fn only_not_found_errors_naming_the_file_report_it_missing() {
+        let body = r#"{"type":"error","error":{"type":"not_found_error","message":"File not found: file_1"}}"#;
+        let missing = ApiErrorResponse::parse(StatusCode::NOT_FOUND, body);
+        assert_eq!(missing.error_type.as_deref(), Some("not_found_error"));
+        assert!(missing.is_missing_file("file_1"));
+        assert!(!missing.is_missing_file("file_2"));
+
+        // A validation error quoting the id is not a missing upload
+        let body = r#"{"type":"error","error":{"type":"invalid_request_error","message":"Bad block for file_1"}}"#;
+        assert!(!ApiErrorResponse::parse(StatusCode::BAD_REQUEST, body).is_missing_file("file_1"));
+
+        let unparsed = ApiErrorResponse::parse(StatusCode::BAD_GATEWAY, "<html>file_1</html>");
+        assert_eq!(unparsed.error_type, None);
+        assert!(!unparsed.is_missing_file("file_1"));
+    }
+}
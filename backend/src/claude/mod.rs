@@ -1,5 +1,6 @@
 pub mod client;
+pub mod retry;
 pub mod types;
 
-pub use client::ClaudeClient;
+pub use client::{ApiErrorResponse, ClaudeClient};
 pub use types::*;
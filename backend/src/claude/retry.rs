@@ -0,0 +1,215 @@
+use crate::config::RetryPolicy;
+use chrono::{DateTime, Utc};
+use reqwest::header::HeaderMap;
+use reqwest::StatusCode;
+use std::time::Duration;
+
+/// Limits whose `anthropic-ratelimit-<limit>-remaining` and `-reset` headers tell a
+/// rate-limited client when it may try again
+const RATE_LIMITS: [&str; 4] = ["requests", "tokens", "input-tokens", "output-tokens"];
+
+/// Whether and when a failed Claude request should be sent again
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetryDecision {
+    /// Auth, validation and other client errors that would fail the same way again
+    Never,
+    /// Retry after an exponential backoff with jitter
+    Backoff,
+    /// Retry after the wait the server asked for
+    After(Duration),
+}
+
+/// Decide whether a response with this status is worth retrying.
+///
+/// Rate limits (429), timeouts (408), overload (529) and other server errors are
+/// retried; an explicit `x-should-retry` header from the API overrides that.
+pub fn classify(status: StatusCode, headers: &HeaderMap) -> RetryDecision {
+    let retryable = match header_str(headers, "x-should-retry") {
+        Some("true") => true,
+        Some("false") => false,
+        _ => {
+            status == StatusCode::TOO_MANY_REQUESTS
+                || status == StatusCode::REQUEST_TIMEOUT
+                || status.is_server_error()
+        }
+    };
+    if !retryable {
+        return RetryDecision::Never;
+    }
+
+    if let Some(wait) = retry_after(headers) {
+        return RetryDecision::After(wait);
+    }
+    if status == StatusCode::TOO_MANY_REQUESTS {
+        if let Some(wait) = rate_limit_reset(headers) {
+            return RetryDecision::After(wait);
+        }
+    }
+
+    RetryDecision::Backoff
+}
+
+/// Exponential backoff for the given retry (0-based), capped at the policy's maximum.
+///
+/// The wait is randomized between half and all of the nominal delay so that clients
+/// rate limited together do not retry in lockstep.
+pub fn backoff_delay(policy: &RetryPolicy, retry: u32) -> Duration {
+    let nominal = policy
+        .base_delay
+        .saturating_mul(2u32.saturating_pow(retry))
+        .min(policy.max_delay);
+    nominal.mul_f64(0.5 + fastrand::f64() * 0.5)
+}
+
+/// Wait requested by `retry-after-ms`, or by `retry-after` as seconds or an HTTP date
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    if let Some(ms) = header_str(headers, "retry-after-ms").and_then(|v| v.trim().parse::<f64>().ok()) {
+        return Duration::try_from_secs_f64(ms / 1000.0).ok();
+    }
+
+    let value = header_str(headers, "retry-after")?.trim();
+    if let Ok(seconds) = value.parse::<f64>() {
+        return Duration::try_from_secs_f64(seconds).ok();
+    }
+    let at = DateTime::parse_from_rfc2822(value).ok()?;
+    Some(until(at.with_timezone(&Utc)))
+}
+
+/// Time until the latest reset among the rate limits that are used up
+fn rate_limit_reset(headers: &HeaderMap) -> Option<Duration> {
+    RATE_LIMITS
+        .iter()
+        .filter(|limit| {
+            header_str(headers, &format!("anthropic-ratelimit-{limit}-remaining"))
+                .and_then(|v| v.trim().parse::<u64>().ok())
+                == Some(0)
+        })
+        .filter_map(|limit| header_str(headers, &format!("anthropic-ratelimit-{limit}-reset")))
+        .filter_map(|reset| DateTime::parse_from_rfc3339(reset.trim()).ok())
+        .map(|reset| until(reset.with_timezone(&Utc)))
+        .max()
+}
+
+fn until(at: DateTime<Utc>) -> Duration {
+    (at - Utc::now()).to_std().unwrap_or(Duration::ZERO)
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|v| v.to_str().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::SecondsFormat;
+
+    fn headers(pairs: &[(&'static str, String)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    /// A wait the server asked for, allowing for the time the test takes to run
+    fn waits_about(decision: RetryDecision, expected: Duration) -> bool {
+        match decision {
+            RetryDecision::After(wait) => wait <= expected && wait + Duration::from_secs(2) >= expected,
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn client_errors_are_never_retried() {
+        for status in [StatusCode::BAD_REQUEST, StatusCode::UNAUTHORIZED, StatusCode::NOT_FOUND] {
+            assert_eq!(classify(status, &HeaderMap::new()), RetryDecision::Never, "{status}");
+        }
+    }
+
+    #[test]
+    fn overload_and_server_errors_back_off() {
+        for status in [
+            StatusCode::REQUEST_TIMEOUT,
+            StatusCode::TOO_MANY_REQUESTS,
+            StatusCode::INTERNAL_SERVER_ERROR,
+            StatusCode::from_u16(529).unwrap(),
+        ] {
+            assert_eq!(classify(status, &HeaderMap::new()), RetryDecision::Backoff, "{status}");
+        }
+    }
+
+    #[test]
+    fn should_retry_header_overrides_the_status() {
+        let retry = headers(&[("x-should-retry", "true".to_string())]);
+        assert_eq!(classify(StatusCode::CONFLICT, &retry), RetryDecision::Backoff);
+
+        let dont = headers(&[("x-should-retry", "false".to_string())]);
+        assert_eq!(classify(StatusCode::SERVICE_UNAVAILABLE, &dont), RetryDecision::Never);
+    }
+
+    #[test]
+    fn retry_after_ms_takes_precedence() {
+        let wait = headers(&[
+            ("retry-after-ms", "1500".to_string()),
+            ("retry-after", "30".to_string()),
+        ]);
+        assert_eq!(
+            classify(StatusCode::TOO_MANY_REQUESTS, &wait),
+            RetryDecision::After(Duration::from_millis(1500))
+        );
+    }
+
+    #[test]
+    fn retry_after_accepts_seconds_and_http_dates() {
+        let seconds = headers(&[("retry-after", "7".to_string())]);
+        assert_eq!(
+            classify(StatusCode::SERVICE_UNAVAILABLE, &seconds),
+            RetryDecision::After(Duration::from_secs(7))
+        );
+
+        let at = (Utc::now() + chrono::Duration::seconds(20))
+            .format("%a, %d %b %Y %H:%M:%S GMT")
+            .to_string();
+        let date = headers(&[("retry-after", at)]);
+        assert!(waits_about(
+            classify(StatusCode::SERVICE_UNAVAILABLE, &date),
+            Duration::from_secs(20)
+        ));
+    }
+
+    #[test]
+    fn rate_limit_waits_for_the_exhausted_limit_to_reset() {
+        let reset = |secs| (Utc::now() + chrono::Duration::seconds(secs)).to_rfc3339_opts(SecondsFormat::Secs, true);
+        let limits = headers(&[
+            ("anthropic-ratelimit-requests-remaining", "12".to_string()),
+            ("anthropic-ratelimit-requests-reset", reset(60)),
+            ("anthropic-ratelimit-input-tokens-remaining", "0".to_string()),
+            ("anthropic-ratelimit-input-tokens-reset", reset(15)),
+        ]);
+
+        assert!(waits_about(
+            classify(StatusCode::TOO_MANY_REQUESTS, &limits),
+            Duration::from_secs(15)
+        ));
+        // Only a rate limit response waits for the reset
+        assert_eq!(classify(StatusCode::INTERNAL_SERVER_ERROR, &limits), RetryDecision::Backoff);
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let policy = RetryPolicy {
+            max_retries: 10,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1),
+        };
+        let within = |retry, nominal: Duration| {
+            let delay = backoff_delay(&policy, retry);
+            delay >= nominal / 2 && delay <= nominal
+        };
+
+        assert!(within(0, Duration::from_millis(100)));
+        assert!(within(2, Duration::from_millis(400)));
+        assert!(within(4, Duration::from_secs(1)));
+        assert!(within(40, Duration::from_secs(1)));
+    }
+}
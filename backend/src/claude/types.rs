@@ -12,6 +12,10 @@ pub enum ContentBlock {
     Document {
         source: DocumentSource,
         #[serde(skip_serializing_if = "Option::is_none")]
+        title: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        citations: Option<CitationsConfig>,
+        #[serde(skip_serializing_if = "Option::is_none")]
         cache_control: Option<CacheControl>,
     },
     Text {
@@ -19,14 +23,58 @@ pub enum ContentBlock {
         #[serde(skip_serializing_if = "Option::is_none")]
         cache_control: Option<CacheControl>,
     },
+    ToolUse {
+        id: String,
+        name: String,
+        input: serde_json::Value,
+    },
+    ToolResult {
+        tool_use_id: String,
+        content: Vec<ContentBlock>,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        is_error: bool,
+    },
+    /// Reasoning from an earlier response, passed back unchanged during tool use
+    Thinking {
+        thinking: String,
+        signature: String,
+    },
+    RedactedThinking {
+        data: String,
+    },
+    /// Any other response block, such as a server tool result, passed back verbatim
+    #[serde(untagged)]
+    Raw(serde_json::Value),
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct DocumentSource {
-    #[serde(rename = "type")]
-    pub source_type: String, // "base64"
-    pub media_type: String,  // "application/pdf"
-    pub data: String,        // base64 encoded PDF
+pub struct CitationsConfig {
+    pub enabled: bool,
+}
+
+/// Where the PDF of a document block comes from
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DocumentSource {
+    /// The whole PDF, base64 encoded, sent with every request
+    Base64 { media_type: String, data: String },
+    /// A PDF uploaded once through the Files API
+    File { file_id: String },
+}
+
+impl DocumentSource {
+    pub fn pdf_base64(data: String) -> Self {
+        Self::Base64 {
+            media_type: "application/pdf".to_string(),
+            data,
+        }
+    }
+}
+
+/// A file stored by the Files API
+#[derive(Debug, Deserialize)]
+pub struct FileMetadata {
+    pub id: String,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -44,29 +92,162 @@ pub struct SystemBlock {
     pub cache_control: Option<CacheControl>,
 }
 
-#[derive(Debug, Serialize)]
+/// A backend-implemented tool Claude may call
+#[derive(Debug, Serialize, Clone)]
+pub struct ToolDefinition {
+    pub name: String,
+    pub description: String,
+    /// JSON schema of the tool input
+    pub input_schema: serde_json::Value,
+}
+
+#[derive(Debug, Serialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ToolChoice {
+    /// Answer without calling any further tools
+    None,
+}
+
+/// Extended thinking settings
+#[derive(Debug, Serialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ThinkingConfig {
+    /// Let Claude reason for up to `budget_tokens` before answering
+    Enabled { budget_tokens: u32 },
+}
+
+#[derive(Debug, Default, Serialize)]
 pub struct ChatRequest {
     pub model: String,
     pub max_tokens: u32,
     pub messages: Vec<Message>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub system: Option<Vec<SystemBlock>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_sequences: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<ToolDefinition>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<ToolChoice>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thinking: Option<ThinkingConfig>,
+}
+
+/// A chat request sent with `stream` enabled
+#[derive(Debug, Serialize)]
+pub struct StreamingRequest<'a> {
+    #[serde(flatten)]
+    pub request: &'a ChatRequest,
+    pub stream: bool,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct ChatResponse {
-    pub id: String,
     pub content: Vec<ResponseContent>,
+    #[serde(default)]
+    pub stop_reason: Option<StopReason>,
     pub usage: Usage,
 }
 
-#[derive(Debug, Deserialize)]
+/// Why Claude stopped generating
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StopReason {
+    EndTurn,
+    /// The reply hit `max_tokens` and was cut off
+    MaxTokens,
+    StopSequence,
+    ToolUse,
+    /// A long-running server tool turn was paused and can be continued
+    PauseTurn,
+    Refusal,
+    ModelContextWindowExceeded,
+    #[serde(other)]
+    Unknown,
+}
+
+/// A block of a response. Block types this backend does not use are still parsed, so
+/// new ones never fail the whole response
+#[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum ResponseContent {
-    Text { text: String },
+    Text {
+        text: String,
+        #[serde(default)]
+        citations: Option<Vec<TextCitation>>,
+    },
+    ToolUse {
+        id: String,
+        name: String,
+        input: serde_json::Value,
+    },
+    Thinking {
+        thinking: String,
+        signature: String,
+    },
+    /// Reasoning the safety systems flagged, returned encrypted
+    RedactedThinking {
+        data: String,
+    },
+    ServerToolUse {
+        id: String,
+        name: String,
+        input: serde_json::Value,
+    },
+    WebSearchToolResult {
+        tool_use_id: String,
+        content: serde_json::Value,
+    },
+    WebFetchToolResult {
+        tool_use_id: String,
+        content: serde_json::Value,
+    },
+    CodeExecutionToolResult {
+        tool_use_id: String,
+        content: serde_json::Value,
+    },
+    BashCodeExecutionToolResult {
+        tool_use_id: String,
+        content: serde_json::Value,
+    },
+    TextEditorCodeExecutionToolResult {
+        tool_use_id: String,
+        content: serde_json::Value,
+    },
+    McpToolUse {
+        id: String,
+        name: String,
+        server_name: String,
+        input: serde_json::Value,
+    },
+    McpToolResult {
+        tool_use_id: String,
+        content: serde_json::Value,
+        #[serde(default)]
+        is_error: bool,
+    },
+    ContainerUpload {
+        file_id: String,
+    },
+    /// A block type added to the API after this was written
+    #[serde(untagged)]
+    Unknown(serde_json::Value),
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+/// A passage from a document block that supports a text block in the response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TextCitation {
+    pub cited_text: String,
+    pub document_index: usize,
+    pub document_title: Option<String>,
+    /// Only present on `page_location` citations; the end page is exclusive
+    pub start_page_number: Option<u32>,
+    pub end_page_number: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct Usage {
     pub input_tokens: u32,
     pub output_tokens: u32,
@@ -76,8 +257,160 @@ pub struct Usage {
     pub cache_read_input_tokens: Option<u32>,
 }
 
+impl Usage {
+    /// Add the usage of another call, e.g. a later round of a tool-use exchange
+    pub fn add(&mut self, other: &Usage) {
+        let sum = |a: Option<u32>, b: Option<u32>| match (a, b) {
+            (None, None) => None,
+            (a, b) => Some(a.unwrap_or(0) + b.unwrap_or(0)),
+        };
+
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+        self.cache_creation_input_tokens =
+            sum(self.cache_creation_input_tokens, other.cache_creation_input_tokens);
+        self.cache_read_input_tokens = sum(self.cache_read_input_tokens, other.cache_read_input_tokens);
+    }
+}
+
+/// Server-sent event emitted by the Messages API when `stream` is enabled
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StreamEvent {
+    MessageStart { message: StreamMessage },
+    ContentBlockStart,
+    ContentBlockDelta { delta: ContentDelta },
+    ContentBlockStop,
+    MessageDelta {
+        #[serde(default)]
+        delta: MessageDeltaBody,
+        usage: DeltaUsage,
+    },
+    MessageStop,
+    Ping,
+    Error { error: StreamError },
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct MessageDeltaBody {
+    #[serde(default)]
+    pub stop_reason: Option<StopReason>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StreamMessage {
+    pub usage: Usage,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentDelta {
+    TextDelta { text: String },
+    CitationsDelta { citation: TextCitation },
+    ThinkingDelta { thinking: String },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeltaUsage {
+    pub output_tokens: u32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StreamError {
+    #[serde(rename = "type")]
+    pub error_type: String,
+    pub message: String,
+}
+
+/// Body of an error response, which carries the same error as a stream `error` event
+#[derive(Debug, Deserialize)]
+pub struct ErrorResponse {
+    pub error: StreamError,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct MetadataExtractionResponse {
     pub keywords: Vec<String>,
     pub topics: Vec<String>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn response(content: serde_json::Value, stop_reason: &str) -> ChatResponse {
+        serde_json::from_value(json!({
+            "content": content,
+            "stop_reason": stop_reason,
+            "usage": { "input_tokens": 1, "output_tokens": 1 }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn unknown_block_types_do_not_fail_the_response() {
+        let parsed = response(
+            json!([
+                { "type": "hologram", "frames": 3 },
+                { "type": "text", "text": "Still here" }
+            ]),
+            "end_turn",
+        );
+
+        assert!(matches!(&parsed.content[0], ResponseContent::Unknown(block) if block["frames"] == 3));
+        assert!(matches!(&parsed.content[1], ResponseContent::Text { text, .. } if text == "Still here"));
+    }
+
+    #[test]
+    fn server_tool_blocks_are_parsed() {
+        let parsed = response(
+            json!([
+                { "type": "server_tool_use", "id": "srvtoolu_1", "name": "web_search", "input": { "query": "fiber" } },
+                { "type": "web_search_tool_result", "tool_use_id": "srvtoolu_1", "content": [] }
+            ]),
+            "end_turn",
+        );
+
+        assert!(matches!(&parsed.content[0], ResponseContent::ServerToolUse { name, .. } if name == "web_search"));
+        assert!(matches!(
+            &parsed.content[1],
+            ResponseContent::WebSearchToolResult { tool_use_id, .. } if tool_use_id == "srvtoolu_1"
+        ));
+    }
+
+    #[test]
+    fn stop_reasons_are_parsed() {
+        assert_eq!(response(json!([]), "max_tokens").stop_reason, Some(StopReason::MaxTokens));
+        assert_eq!(response(json!([]), "pause_turn").stop_reason, Some(StopReason::PauseTurn));
+        assert_eq!(response(json!([]), "out_of_coffee").stop_reason, Some(StopReason::Unknown));
+    }
+
+    #[test]
+    fn message_delta_carries_the_stop_reason() {
+        let event: StreamEvent = serde_json::from_value(json!({
+            "type": "message_delta",
+            "delta": { "stop_reason": "max_tokens", "stop_sequence": null },
+            "usage": { "output_tokens": 42 }
+        }))
+        .unwrap();
+
+        match event {
+            StreamEvent::MessageDelta { delta, usage } => {
+                assert_eq!(delta.stop_reason, Some(StopReason::MaxTokens));
+                assert_eq!(usage.output_tokens, 42);
+            }
+            other => panic!("Expected a message delta, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn unknown_stream_events_are_skipped() {
+        let event: StreamEvent = serde_json::from_value(json!({ "type": "telemetry", "data": 1 })).unwrap();
+        assert!(matches!(event, StreamEvent::Unknown));
+    }
+}
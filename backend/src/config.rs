@@ -0,0 +1,548 @@
+use crate::claude::Usage;
+use crate::error::ApiError;
+use crate::models::GenerationOptions;
+use std::collections::HashMap;
+use std::time::Duration;
+
+const DEFAULT_MODEL: &str = "claude-sonnet-4-5-20250929";
+const DEFAULT_ALLOWED_MODELS: &str =
+    "claude-sonnet-4-5-20250929,claude-haiku-4-5-20251001,claude-opus-4-1-20250805";
+const DEFAULT_MAX_TOKENS: u32 = 4096;
+const DEFAULT_MAX_TOKENS_LIMIT: u32 = 8192;
+const MAX_STOP_SEQUENCES: usize = 4;
+const MIN_THINKING_BUDGET: u32 = 1024;
+const DEFAULT_PRICES: &str =
+    "claude-sonnet-4-5-20250929=3:15,claude-haiku-4-5-20251001=1:5,claude-opus-4-1-20250805=15:75";
+
+const DEFAULT_BASE_URL: &str = "https://api.anthropic.com";
+const DEFAULT_API_VERSION: &str = "2023-06-01";
+const DEFAULT_BETA: &str = "prompt-caching-2024-07-31";
+const FILES_API_BETA: &str = "files-api-2025-04-14";
+const DEFAULT_FILES_RECHECK_SECS: u64 = 600;
+const DEFAULT_MAX_RETRIES: u32 = 3;
+const DEFAULT_RETRY_BASE_MS: u64 = 1000;
+const DEFAULT_RETRY_MAX_MS: u64 = 60_000;
+
+const DEFAULT_HISTORY_KEEP_TURNS: usize = 6;
+const DEFAULT_HISTORY_TOKEN_BUDGET: usize = 40_000;
+
+// Prompt caching multipliers relative to the base input price
+const CACHE_WRITE_MULTIPLIER: f64 = 1.25;
+const CACHE_READ_MULTIPLIER: f64 = 0.1;
+
+/// A model clients may request, with its own cap on `max_tokens`
+#[derive(Debug, Clone)]
+pub struct AllowedModel {
+    pub name: String,
+    pub max_tokens_limit: u32,
+}
+
+/// Server-side limits on the models and generation parameters clients can choose
+#[derive(Debug, Clone)]
+pub struct ModelPolicy {
+    pub default_model: String,
+    pub default_max_tokens: u32,
+    pub allowed_models: Vec<AllowedModel>,
+}
+
+/// Generation parameters after validation against the policy
+#[derive(Debug, Clone)]
+pub struct GenerationParams {
+    pub model: String,
+    pub max_tokens: u32,
+    pub temperature: Option<f32>,
+    pub stop_sequences: Option<Vec<String>>,
+    pub thinking_budget: Option<u32>,
+}
+
+impl ModelPolicy {
+    /// Load the policy from the environment.
+    ///
+    /// `CLAUDE_ALLOWED_MODELS` is a comma-separated list of `model` or `model=max_tokens`
+    /// entries; models without an explicit limit use `CLAUDE_MAX_TOKENS_LIMIT`.
+    pub fn from_env() -> Self {
+        let default_limit = env_parse("CLAUDE_MAX_TOKENS_LIMIT").unwrap_or(DEFAULT_MAX_TOKENS_LIMIT);
+        let default_model =
+            std::env::var("CLAUDE_DEFAULT_MODEL").unwrap_or_else(|_| DEFAULT_MODEL.to_string());
+        let allowed = std::env::var("CLAUDE_ALLOWED_MODELS")
+            .unwrap_or_else(|_| DEFAULT_ALLOWED_MODELS.to_string());
+
+        let mut allowed_models = parse_allowed_models(&allowed, default_limit);
+
+        // The default model is always allowed
+        if !allowed_models.iter().any(|m| m.name == default_model) {
+            allowed_models.push(AllowedModel {
+                name: default_model.clone(),
+                max_tokens_limit: default_limit,
+            });
+        }
+
+        Self {
+            default_model,
+            default_max_tokens: env_parse("CLAUDE_DEFAULT_MAX_TOKENS").unwrap_or(DEFAULT_MAX_TOKENS),
+            allowed_models,
+        }
+    }
+
+    /// Resolve requested parameters, filling in defaults and rejecting anything out of bounds
+    pub fn resolve(&self, options: &GenerationOptions) -> Result<GenerationParams, ApiError> {
+        let model = options.model.as_deref().unwrap_or(&self.default_model);
+        let allowed = self
+            .allowed_models
+            .iter()
+            .find(|m| m.name == model)
+            .ok_or_else(|| ApiError::BadRequest(format!("Model is not allowed: {}", model)))?;
+
+        let max_tokens = options.max_tokens.unwrap_or(self.default_max_tokens.min(allowed.max_tokens_limit));
+        if max_tokens == 0 || max_tokens > allowed.max_tokens_limit {
+            return Err(ApiError::BadRequest(format!(
+                "max_tokens must be between 1 and {} for {}",
+                allowed.max_tokens_limit, model
+            )));
+        }
+
+        if let Some(temperature) = options.temperature {
+            if !(0.0..=1.0).contains(&temperature) {
+                return Err(ApiError::BadRequest(
+                    "temperature must be between 0.0 and 1.0".to_string(),
+                ));
+            }
+        }
+
+        if let Some(stop_sequences) = &options.stop_sequences {
+            if stop_sequences.len() > MAX_STOP_SEQUENCES {
+                return Err(ApiError::BadRequest(format!(
+                    "At most {} stop sequences are allowed",
+                    MAX_STOP_SEQUENCES
+                )));
+            }
+            if stop_sequences.iter().any(|s| s.trim().is_empty()) {
+                return Err(ApiError::BadRequest(
+                    "Stop sequences must not be empty".to_string(),
+                ));
+            }
+        }
+
+        if let Some(budget) = options.thinking_budget {
+            if budget < MIN_THINKING_BUDGET || budget >= max_tokens {
+                return Err(ApiError::BadRequest(format!(
+                    "thinking_budget must be at least {} and less than max_tokens ({})",
+                    MIN_THINKING_BUDGET, max_tokens
+                )));
+            }
+            if options.temperature.is_some() {
+                return Err(ApiError::BadRequest(
+                    "temperature cannot be set when thinking is enabled".to_string(),
+                ));
+            }
+        } else if options.include_thinking {
+            return Err(ApiError::BadRequest(
+                "include_thinking requires a thinking_budget".to_string(),
+            ));
+        }
+
+        Ok(GenerationParams {
+            model: model.to_string(),
+            max_tokens,
+            temperature: options.temperature,
+            stop_sequences: options.stop_sequences.clone().filter(|s| !s.is_empty()),
+            thinking_budget: options.thinking_budget,
+        })
+    }
+}
+
+/// Parse `model` and `model=max_tokens` entries, logging any that are malformed.
+///
+/// An entry whose limit does not parse keeps the model at `default_limit`.
+fn parse_allowed_models(raw: &str, default_limit: u32) -> Vec<AllowedModel> {
+    raw.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .filter_map(|entry| {
+            let (name, limit) = match entry.split_once('=') {
+                Some((name, limit)) => (name.trim(), Some(limit.trim())),
+                None => (entry, None),
+            };
+            if name.is_empty() {
+                eprintln!("Ignoring CLAUDE_ALLOWED_MODELS entry without a model: {}", entry);
+                return None;
+            }
+
+            let max_tokens_limit = match limit.map(str::parse::<u32>) {
+                None => default_limit,
+                Some(Ok(limit)) if limit > 0 => limit,
+                Some(_) => {
+                    eprintln!(
+                        "Invalid max_tokens limit in CLAUDE_ALLOWED_MODELS entry {}; using {}",
+                        entry, default_limit
+                    );
+                    default_limit
+                }
+            };
+
+            Some(AllowedModel {
+                name: name.to_string(),
+                max_tokens_limit,
+            })
+        })
+        .collect()
+}
+
+/// Where the Claude client sends requests and which API version and betas it asks for
+#[derive(Debug, Clone)]
+pub struct ClaudeEndpoint {
+    /// Base URL the Messages API path is appended to, without a trailing slash
+    pub base_url: String,
+    pub api_version: String,
+    /// Beta features sent in the `anthropic-beta` header; empty sends no header
+    pub betas: Vec<String>,
+}
+
+impl ClaudeEndpoint {
+    /// Load the endpoint from `ANTHROPIC_BASE_URL`, `ANTHROPIC_VERSION` and
+    /// `ANTHROPIC_BETA`, a comma-separated list of beta names
+    pub fn from_env() -> Self {
+        let base_url =
+            std::env::var("ANTHROPIC_BASE_URL").unwrap_or_else(|_| DEFAULT_BASE_URL.to_string());
+        let api_version =
+            std::env::var("ANTHROPIC_VERSION").unwrap_or_else(|_| DEFAULT_API_VERSION.to_string());
+        let betas = std::env::var("ANTHROPIC_BETA")
+            .unwrap_or_else(|_| DEFAULT_BETA.to_string())
+            .split(',')
+            .map(|beta| beta.trim().to_string())
+            .filter(|beta| !beta.is_empty())
+            .collect();
+
+        Self {
+            base_url: base_url.trim().trim_end_matches('/').to_string(),
+            api_version: api_version.trim().to_string(),
+            betas,
+        }
+    }
+
+    /// Ask for a beta feature unless it is already enabled
+    pub fn enable_beta(&mut self, beta: &str) {
+        if !self.betas.iter().any(|b| b == beta) {
+            self.betas.push(beta.to_string());
+        }
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+}
+
+/// Whether PDFs are uploaded once through the Files API and referenced by id instead
+/// of being sent as base64 with every request
+#[derive(Debug, Clone)]
+pub struct FilesApiPolicy {
+    pub enabled: bool,
+    /// How long an upload is trusted before checking that the Files API still has it
+    pub recheck_after: Duration,
+}
+
+impl FilesApiPolicy {
+    /// Load the policy from `CLAUDE_FILES_API` and `CLAUDE_FILES_RECHECK_SECS`
+    pub fn from_env() -> Self {
+        Self {
+            enabled: env_parse("CLAUDE_FILES_API").unwrap_or(false),
+            recheck_after: Duration::from_secs(
+                env_parse("CLAUDE_FILES_RECHECK_SECS").unwrap_or(DEFAULT_FILES_RECHECK_SECS),
+            ),
+        }
+    }
+
+    /// Turn on the beta the Files API and file document sources need
+    pub fn configure(&self, endpoint: &mut ClaudeEndpoint) {
+        if self.enabled {
+            endpoint.enable_beta(FILES_API_BETA);
+        }
+    }
+}
+
+/// How the Claude client retries rate-limited, overloaded and failed requests
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt; 0 disables retrying
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled for each one after it
+    pub base_delay: Duration,
+    /// Longest wait before a retry; a server asking for more fails the request instead
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    pub fn from_env() -> Self {
+        Self {
+            max_retries: env_parse("CLAUDE_MAX_RETRIES").unwrap_or(DEFAULT_MAX_RETRIES),
+            base_delay: Duration::from_millis(
+                env_parse("CLAUDE_RETRY_BASE_MS").unwrap_or(DEFAULT_RETRY_BASE_MS),
+            ),
+            max_delay: Duration::from_millis(
+                env_parse("CLAUDE_RETRY_MAX_MS").unwrap_or(DEFAULT_RETRY_MAX_MS),
+            ),
+        }
+    }
+}
+
+/// When to fold older conversation turns into a rolling summary
+#[derive(Debug, Clone)]
+pub struct HistoryPolicy {
+    /// Number of most recent user/assistant turns always sent verbatim
+    pub keep_turns: usize,
+    /// Estimated history size, in tokens, above which older turns are summarized
+    pub token_budget: usize,
+}
+
+impl HistoryPolicy {
+    pub fn from_env() -> Self {
+        Self {
+            keep_turns: env_parse("HISTORY_KEEP_TURNS").unwrap_or(DEFAULT_HISTORY_KEEP_TURNS),
+            token_budget: env_parse("HISTORY_TOKEN_BUDGET").unwrap_or(DEFAULT_HISTORY_TOKEN_BUDGET),
+        }
+    }
+}
+
+/// Price of a model in USD per million tokens
+#[derive(Debug, Clone, Copy)]
+pub struct ModelPrice {
+    pub input: f64,
+    pub output: f64,
+}
+
+/// Cost of a single Claude call, with and without prompt caching
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CallCost {
+    pub cost_usd: f64,
+    /// What the call would have cost if every cached token had been billed as plain input
+    pub uncached_cost_usd: f64,
+}
+
+/// Per-model prices used to cost recorded usage
+#[derive(Debug, Clone)]
+pub struct PriceTable {
+    prices: HashMap<String, ModelPrice>,
+}
+
+impl PriceTable {
+    /// Load prices from `CLAUDE_PRICES`, a comma-separated list of `model=input:output`
+    /// entries in USD per million tokens
+    pub fn from_env() -> Self {
+        let raw = std::env::var("CLAUDE_PRICES").unwrap_or_else(|_| DEFAULT_PRICES.to_string());
+        Self::parse(&raw)
+    }
+
+    fn parse(raw: &str) -> Self {
+        let prices = raw
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .filter_map(|entry| {
+                let price = entry.split_once('=').and_then(|(model, price)| {
+                    let model = model.trim();
+                    let (input, output) = price.split_once(':')?;
+                    if model.is_empty() {
+                        return None;
+                    }
+                    let price = ModelPrice {
+                        input: input.trim().parse().ok()?,
+                        output: output.trim().parse().ok()?,
+                    };
+                    Some((model.to_string(), price))
+                });
+                if price.is_none() {
+                    eprintln!("Ignoring malformed CLAUDE_PRICES entry: {}", entry);
+                }
+                price
+            })
+            .collect();
+
+        Self { prices }
+    }
+
+    /// Cost a call; models without a configured price cost nothing
+    pub fn cost(&self, model: &str, usage: &Usage) -> CallCost {
+        let Some(price) = self.prices.get(model) else {
+            return CallCost::default();
+        };
+
+        let per_token = |usd_per_mtok: f64| usd_per_mtok / 1_000_000.0;
+        let input = f64::from(usage.input_tokens);
+        let output = f64::from(usage.output_tokens);
+        let cache_write = f64::from(usage.cache_creation_input_tokens.unwrap_or(0));
+        let cache_read = f64::from(usage.cache_read_input_tokens.unwrap_or(0));
+
+        let output_cost = output * per_token(price.output);
+        let cost_usd = input * per_token(price.input)
+            + cache_write * per_token(price.input * CACHE_WRITE_MULTIPLIER)
+            + cache_read * per_token(price.input * CACHE_READ_MULTIPLIER)
+            + output_cost;
+        let uncached_cost_usd =
+            (input + cache_write + cache_read) * per_token(price.input) + output_cost;
+
+        CallCost {
+            cost_usd,
+            uncached_cost_usd,
+        }
+    }
+}
+
+/// Parse an environment variable, logging a value that is set but malformed
+fn env_parse<T: std::str::FromStr>(name: &str) -> Option<T> {
+    let value = std::env::var(name).ok()?;
+    let parsed = value.trim().parse().ok();
+    if parsed.is_none() {
+        eprintln!("Ignoring invalid value for {}: {:?}", name, value);
+    }
+    parsed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> ModelPolicy {
+        ModelPolicy {
+            default_model: "default-model".to_string(),
+            default_max_tokens: 4096,
+            allowed_models: parse_allowed_models("default-model,small-model=1000", 8192),
+        }
+    }
+
+    fn rejects(options: GenerationOptions) -> bool {
+        matches!(policy().resolve(&options), Err(ApiError::BadRequest(_)))
+    }
+
+    #[test]
+    fn parses_allowed_models_and_falls_back_on_bad_limits() {
+        let models = parse_allowed_models(" a , b=100, c=abc, =5, d=0 ,", 8192);
+        let parsed: Vec<(&str, u32)> = models
+            .iter()
+            .map(|m| (m.name.as_str(), m.max_tokens_limit))
+            .collect();
+        assert_eq!(parsed, vec![("a", 8192), ("b", 100), ("c", 8192), ("d", 8192)]);
+    }
+
+    #[test]
+    fn fills_in_defaults() {
+        let params = policy().resolve(&GenerationOptions::default()).unwrap();
+        assert_eq!(params.model, "default-model");
+        assert_eq!(params.max_tokens, 4096);
+
+        let small = GenerationOptions {
+            model: Some("small-model".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(policy().resolve(&small).unwrap().max_tokens, 1000);
+    }
+
+    #[test]
+    fn rejects_unknown_models() {
+        assert!(rejects(GenerationOptions {
+            model: Some("other-model".to_string()),
+            ..Default::default()
+        }));
+    }
+
+    #[test]
+    fn caps_max_tokens_per_model() {
+        let with = |model: &str, max_tokens| GenerationOptions {
+            model: Some(model.to_string()),
+            max_tokens: Some(max_tokens),
+            ..Default::default()
+        };
+        assert!(rejects(with("default-model", 0)));
+        assert!(rejects(with("small-model", 1001)));
+        assert!(policy().resolve(&with("small-model", 1000)).is_ok());
+        assert!(policy().resolve(&with("default-model", 8192)).is_ok());
+    }
+
+    #[test]
+    fn limits_temperature_and_stop_sequences() {
+        assert!(rejects(GenerationOptions {
+            temperature: Some(1.5),
+            ..Default::default()
+        }));
+
+        let stops = |stops: &[&str]| GenerationOptions {
+            stop_sequences: Some(stops.iter().map(|s| s.to_string()).collect()),
+            ..Default::default()
+        };
+        assert!(rejects(stops(&["a", "b", "c", "d", "e"])));
+        assert!(rejects(stops(&["a", " "])));
+        assert!(policy().resolve(&stops(&["a", "b", "c", "d"])).is_ok());
+        assert_eq!(policy().resolve(&stops(&[])).unwrap().stop_sequences, None);
+    }
+
+    #[test]
+    fn validates_thinking_budget() {
+        let thinking = |budget, temperature| GenerationOptions {
+            thinking_budget: Some(budget),
+            temperature,
+            ..Default::default()
+        };
+        assert!(rejects(thinking(MIN_THINKING_BUDGET - 1, None)));
+        assert!(rejects(thinking(4096, None)), "budget must stay below max_tokens");
+        assert!(rejects(thinking(2048, Some(0.5))), "temperature is not allowed with thinking");
+        assert_eq!(
+            policy().resolve(&thinking(2048, None)).unwrap().thinking_budget,
+            Some(2048)
+        );
+
+        assert!(rejects(GenerationOptions {
+            include_thinking: true,
+            ..Default::default()
+        }));
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-12, "{actual} != {expected}");
+    }
+
+    #[test]
+    fn costs_plain_input_and_output() {
+        let prices = PriceTable::parse("model-a=3:15");
+        let usage = Usage {
+            input_tokens: 1_000_000,
+            output_tokens: 100_000,
+            ..Default::default()
+        };
+        let cost = prices.cost("model-a", &usage);
+        assert_close(cost.cost_usd, 3.0 + 1.5);
+        assert_close(cost.uncached_cost_usd, cost.cost_usd);
+    }
+
+    #[test]
+    fn prices_cache_writes_and_reads() {
+        let prices = PriceTable::parse("model-a=3:15");
+        let usage = Usage {
+            input_tokens: 1000,
+            output_tokens: 500,
+            cache_creation_input_tokens: Some(2000),
+            cache_read_input_tokens: Some(10_000),
+        };
+        let cost = prices.cost("model-a", &usage);
+        // 1000 x $3 + 2000 x $3.75 + 10000 x $0.30 + 500 x $15, per million tokens
+        assert_close(cost.cost_usd, 0.003 + 0.0075 + 0.003 + 0.0075);
+        // Every input token at $3, plus output
+        assert_close(cost.uncached_cost_usd, 0.039 + 0.0075);
+    }
+
+    #[test]
+    fn unknown_models_and_malformed_prices_cost_nothing() {
+        let prices = PriceTable::parse("model-a=3:15, model-b=oops, model-c=1, =1:2,");
+        let usage = Usage {
+            input_tokens: 1000,
+            output_tokens: 1000,
+            ..Default::default()
+        };
+        // Malformed entries are skipped without losing the valid ones
+        assert_close(prices.cost("model-a", &usage).cost_usd, 0.003 + 0.015);
+        assert_eq!(prices.prices.len(), 1);
+        for model in ["model-b", "model-c", "model-z"] {
+            let cost = prices.cost(model, &usage);
+            assert_eq!(cost.cost_usd, 0.0);
+            assert_eq!(cost.uncached_cost_usd, 0.0);
+        }
+    }
+}
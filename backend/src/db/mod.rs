@@ -1,5 +1,9 @@
 mod schema;
 mod queries;
 
-pub use queries::{ChatDatabase, Conversation, Document, StoredMessage};
+pub use queries::{
+    ChatDatabase, CompletedMessage, Conversation, Document, DocumentArtifact, Feedback,
+    MessageSearchHit, NewMessage, NewUsageRecord, PromptPreset, RatedExchange, StoredMessage,
+    UsageSummary,
+};
 pub use schema::initialize_database;
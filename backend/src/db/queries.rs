@@ -1,21 +1,195 @@
+use crate::models::{
+    ChatApiResponse, Citation, DocumentQuote, FeedbackCategory, MessageStatus, Rating, Selection,
+    ToolCall, TurnStatus,
+};
 use chrono::Utc;
 use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
 use sqlx::{FromRow, SqlitePool};
 use uuid::Uuid;
 
 #[derive(Debug, Clone, FromRow, Serialize)]
 pub struct StoredMessage {
     pub id: String,
+    pub parent_message_id: Option<String>,
     pub role: String,
     pub content: String,
+    pub citations: Option<Json<Vec<Citation>>>,
+    pub quotes: Option<Json<Vec<DocumentQuote>>>,
+    pub model: Option<String>,
+    pub tool_calls: Option<Json<Vec<ToolCall>>>,
+    /// Passage a user message asked about
+    pub selection: Option<Json<Selection>>,
+    pub status: MessageStatus,
+    /// Why generating the message failed
+    pub error: Option<String>,
+    /// Claude's reasoning, stored when the client asked for it
+    pub thinking: Option<String>,
     pub created_at: String,
 }
 
+/// Token usage and cost of a single Claude call, to be recorded
+#[derive(Debug, Default)]
+pub struct NewUsageRecord<'a> {
+    /// What the call was for, e.g. "chat" or "metadata"
+    pub kind: &'a str,
+    pub conversation_id: Option<&'a str>,
+    pub document_id: Option<&'a str>,
+    pub message_id: Option<&'a str>,
+    pub model: &'a str,
+    pub input_tokens: u32,
+    pub output_tokens: u32,
+    pub cache_creation_input_tokens: u32,
+    pub cache_read_input_tokens: u32,
+    pub cost_usd: f64,
+    pub uncached_cost_usd: f64,
+}
+
+/// Aggregated usage over a set of calls
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct UsageSummary {
+    pub calls: i64,
+    pub input_tokens: i64,
+    pub output_tokens: i64,
+    pub cache_creation_input_tokens: i64,
+    pub cache_read_input_tokens: i64,
+    pub cost_usd: f64,
+    pub uncached_cost_usd: f64,
+}
+
+/// Aggregated usage for one calendar day (UTC)
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct DailyUsage {
+    pub day: String,
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub summary: UsageSummary,
+}
+
+/// A message to be inserted into a conversation
+#[derive(Debug, Default)]
+pub struct NewMessage<'a> {
+    pub conversation_id: &'a str,
+    /// Message this one replies to or follows; `None` for the first message of a branch
+    pub parent_message_id: Option<&'a str>,
+    pub role: &'a str,
+    pub content: &'a str,
+    pub citations: Option<&'a [Citation]>,
+    pub quotes: Option<&'a [DocumentQuote]>,
+    pub model: Option<&'a str>,
+    pub tool_calls: Option<&'a [ToolCall]>,
+    pub selection: Option<&'a Selection>,
+    pub status: MessageStatus,
+}
+
+/// A finished reply to fill a pending assistant message with
+#[derive(Debug)]
+pub struct CompletedMessage<'a> {
+    pub content: &'a str,
+    pub citations: &'a [Citation],
+    pub quotes: &'a [DocumentQuote],
+    pub model: &'a str,
+    pub tool_calls: Option<&'a [ToolCall]>,
+    pub thinking: Option<&'a str>,
+}
+
+/// A user's rating of an assistant message
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct Feedback {
+    pub message_id: String,
+    pub rating: Rating,
+    pub category: Option<FeedbackCategory>,
+    pub comment: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+/// A rated assistant answer together with the question it answered
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct RatedExchange {
+    pub message_id: String,
+    pub conversation_id: String,
+    pub document_id: String,
+    pub question: Option<String>,
+    pub answer: String,
+    pub citations: Option<Json<Vec<Citation>>>,
+    pub quotes: Option<Json<Vec<DocumentQuote>>>,
+    pub model: Option<String>,
+    pub rating: Rating,
+    pub category: Option<FeedbackCategory>,
+    pub comment: Option<String>,
+    pub rated_at: String,
+}
+
 #[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
 pub struct Conversation {
     pub id: String,
     pub document_id: String,
     pub title: Option<String>,
+    /// Rolling summary of messages up to and including `summarized_until`
+    pub summary: Option<String>,
+    /// `created_at` of the last message folded into `summary`
+    pub summarized_until: Option<String>,
+    pub summarization_enabled: bool,
+    /// Last message of the branch currently shown
+    pub active_leaf_id: Option<String>,
+    /// Prompt preset used as the system prompt; the default prompt when unset
+    pub preset_id: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+/// A message matching a full-text search, with where to find it
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct MessageSearchHit {
+    pub message_id: String,
+    pub role: String,
+    pub conversation_id: String,
+    pub conversation_title: Option<String>,
+    pub document_id: String,
+    pub document_filename: Option<String>,
+    /// Excerpt around the match, with matched terms highlighted
+    pub snippet: String,
+    pub created_at: String,
+}
+
+/// The stored result of a document action; each refresh adds a new version
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct DocumentArtifact {
+    pub id: String,
+    pub document_id: String,
+    pub action: String,
+    pub version: i64,
+    pub content: String,
+    pub model: String,
+    pub created_at: String,
+}
+
+/// A named system prompt that conversations can use
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct PromptPreset {
+    pub id: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub system_prompt: String,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+/// A chat request and, once it has finished, its outcome
+#[derive(Debug, FromRow, Serialize)]
+pub struct ChatTurn {
+    pub id: String,
+    pub idempotency_key: Option<String>,
+    /// SHA-256 of the request body, to spot a key reused for a different request
+    pub request_hash: String,
+    pub document_id: String,
+    pub conversation_id: Option<String>,
+    /// The assistant message the turn fills in, once it is stored
+    pub message_id: Option<String>,
+    pub status: TurnStatus,
+    pub response: Option<Json<ChatApiResponse>>,
+    pub error: Option<String>,
     pub created_at: String,
     pub updated_at: String,
 }
@@ -42,18 +216,7 @@ impl ChatDatabase {
     }
 
     pub async fn get_or_create_conversation(&self, document_id: &str) -> Result<String, sqlx::Error> {
-        // Ensure document exists in database (for backward compatibility with old uploads)
-        let doc_exists: Option<(String,)> = sqlx::query_as(
-            "SELECT id FROM documents WHERE id = ?",
-        )
-        .bind(document_id)
-        .fetch_optional(&self.pool)
-        .await?;
-
-        if doc_exists.is_none() {
-            // Create document record with default filename for backward compatibility
-            self.create_document(document_id, "unknown.pdf").await?;
-        }
+        self.ensure_document(document_id).await?;
 
         // Check if conversation exists
         let existing: Option<(String,)> = sqlx::query_as(
@@ -65,12 +228,7 @@ impl ChatDatabase {
 
         if let Some((conversation_id,)) = existing {
             // Update the updated_at timestamp
-            let now = Utc::now().to_rfc3339();
-            sqlx::query("UPDATE conversations SET updated_at = ? WHERE id = ?")
-                .bind(&now)
-                .bind(&conversation_id)
-                .execute(&self.pool)
-                .await?;
+            self.touch_conversation(&conversation_id).await?;
             Ok(conversation_id)
         } else {
             // Create new conversation
@@ -91,42 +249,160 @@ impl ChatDatabase {
         }
     }
 
-    pub async fn save_message(
-        &self,
-        conversation_id: &str,
-        role: &str,
-        content: &str,
-    ) -> Result<String, sqlx::Error> {
+    /// Ensure a document record exists (for backward compatibility with old uploads)
+    pub async fn ensure_document(&self, document_id: &str) -> Result<(), sqlx::Error> {
+        if self.get_document(document_id).await?.is_none() {
+            // Create document record with default filename for backward compatibility
+            self.create_document(document_id, "unknown.pdf").await?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn touch_conversation(&self, conversation_id: &str) -> Result<(), sqlx::Error> {
+        let now = Utc::now().to_rfc3339();
+
+        sqlx::query("UPDATE conversations SET updated_at = ? WHERE id = ?")
+            .bind(&now)
+            .bind(conversation_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn save_message(&self, message: NewMessage<'_>) -> Result<String, sqlx::Error> {
         let message_id = Uuid::new_v4().to_string();
         let created_at = Utc::now().to_rfc3339();
 
+        let mut tx = self.pool.begin().await?;
+
         sqlx::query(
-            "INSERT INTO chat_messages (id, conversation_id, role, content, created_at) VALUES (?, ?, ?, ?, ?)",
+            r#"
+            INSERT INTO chat_messages (
+                id, conversation_id, parent_message_id, role, content, citations, quotes, model,
+                tool_calls, selection, status, created_at
+            )
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
         )
         .bind(&message_id)
-        .bind(conversation_id)
-        .bind(role)
-        .bind(content)
+        .bind(message.conversation_id)
+        .bind(message.parent_message_id)
+        .bind(message.role)
+        .bind(message.content)
+        .bind(message.citations.map(Json))
+        .bind(message.quotes.map(Json))
+        .bind(message.model)
+        .bind(message.tool_calls.map(Json))
+        .bind(message.selection.map(Json))
+        .bind(message.status)
         .bind(&created_at)
-        .execute(&self.pool)
+        .execute(&mut *tx)
         .await?;
 
+        // Keep the full-text index in sync; pending messages are indexed once complete
+        if message.status == MessageStatus::Complete {
+            sqlx::query("INSERT INTO chat_messages_fts (message_id, content) VALUES (?, ?)")
+                .bind(&message_id)
+                .bind(message.content)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+
         Ok(message_id)
     }
 
+    /// Fill in a pending assistant message with the finished reply
+    pub async fn complete_message(
+        &self,
+        message_id: &str,
+        reply: CompletedMessage<'_>,
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query(
+            r#"
+            UPDATE chat_messages
+            SET content = ?, citations = ?, quotes = ?, model = ?, tool_calls = ?, thinking = ?,
+                status = ?, error = NULL
+            WHERE id = ?
+            "#,
+        )
+        .bind(reply.content)
+        .bind(Json(reply.citations))
+        .bind(Json(reply.quotes))
+        .bind(reply.model)
+        .bind(reply.tool_calls.map(Json))
+        .bind(reply.thinking)
+        .bind(MessageStatus::Complete)
+        .bind(message_id)
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query("INSERT INTO chat_messages_fts (message_id, content) VALUES (?, ?)")
+            .bind(message_id)
+            .bind(reply.content)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Mark a pending message as failed. Messages that are no longer pending are left alone
+    pub async fn fail_message(&self, message_id: &str, error: &str) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE chat_messages SET status = ?, error = ? WHERE id = ? AND status = ?")
+            .bind(MessageStatus::Failed)
+            .bind(error)
+            .bind(message_id)
+            .bind(MessageStatus::Pending)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Mark a failed message as pending again before retrying it. Returns false if it
+    /// is not failed, e.g. because another retry is already running
+    pub async fn restart_message(&self, message_id: &str) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(
+            "UPDATE chat_messages SET status = ?, error = NULL WHERE id = ? AND status = ?",
+        )
+        .bind(MessageStatus::Pending)
+        .bind(message_id)
+        .bind(MessageStatus::Failed)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Get the active branch of every conversation on a document
     pub async fn get_conversation_messages(
         &self,
         document_id: &str,
     ) -> Result<Vec<StoredMessage>, sqlx::Error> {
-        let messages: Vec<StoredMessage> = sqlx::query_as(
+        let messages: Vec<StoredMessage> = sqlx::query_as(&format!(
             r#"
-            SELECT m.id, m.role, m.content, m.created_at
-            FROM chat_messages m
-            JOIN conversations c ON m.conversation_id = c.id
-            WHERE c.document_id = ?
-            ORDER BY m.created_at ASC
+            WITH RECURSIVE path(id) AS (
+                SELECT active_leaf_id FROM conversations
+                WHERE document_id = ? AND active_leaf_id IS NOT NULL
+                UNION ALL
+                SELECT m.parent_message_id FROM chat_messages m
+                JOIN path p ON m.id = p.id
+                WHERE m.parent_message_id IS NOT NULL
+            )
+            SELECT {}
+            FROM chat_messages
+            WHERE id IN (SELECT id FROM path)
+            ORDER BY created_at ASC
             "#,
-        )
+            MESSAGE_COLUMNS
+        ))
         .bind(document_id)
         .fetch_all(&self.pool)
         .await?;
@@ -134,6 +410,114 @@ impl ChatDatabase {
         Ok(messages)
     }
 
+    pub async fn get_message(
+        &self,
+        conversation_id: &str,
+        message_id: &str,
+    ) -> Result<Option<StoredMessage>, sqlx::Error> {
+        let message: Option<StoredMessage> = sqlx::query_as(&format!(
+            "SELECT {} FROM chat_messages WHERE id = ? AND conversation_id = ?",
+            MESSAGE_COLUMNS
+        ))
+        .bind(message_id)
+        .bind(conversation_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(message)
+    }
+
+    /// Get the path from the root of the tree down to (and including) a message
+    pub async fn get_message_path(&self, message_id: &str) -> Result<Vec<StoredMessage>, sqlx::Error> {
+        let messages: Vec<StoredMessage> = sqlx::query_as(&format!(
+            r#"
+            WITH RECURSIVE path(id, depth) AS (
+                SELECT ?, 0
+                UNION ALL
+                SELECT m.parent_message_id, p.depth + 1 FROM chat_messages m
+                JOIN path p ON m.id = p.id
+                WHERE m.parent_message_id IS NOT NULL
+            )
+            SELECT {}
+            FROM chat_messages
+            JOIN path ON chat_messages.id = path.id
+            ORDER BY path.depth DESC
+            "#,
+            MESSAGE_COLUMNS
+        ))
+        .bind(message_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(messages)
+    }
+
+    /// List the messages sharing a parent, oldest first
+    pub async fn list_sibling_messages(
+        &self,
+        conversation_id: &str,
+        parent_message_id: Option<&str>,
+    ) -> Result<Vec<StoredMessage>, sqlx::Error> {
+        let messages: Vec<StoredMessage> = sqlx::query_as(&format!(
+            r#"
+            SELECT {}
+            FROM chat_messages
+            WHERE conversation_id = ? AND parent_message_id IS ?
+            ORDER BY created_at ASC
+            "#,
+            MESSAGE_COLUMNS
+        ))
+        .bind(conversation_id)
+        .bind(parent_message_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(messages)
+    }
+
+    /// Find the leaf reached from a message by always following its most recent child
+    pub async fn latest_leaf(&self, message_id: &str) -> Result<String, sqlx::Error> {
+        let (leaf,): (String,) = sqlx::query_as(
+            r#"
+            WITH RECURSIVE descend(id, depth) AS (
+                SELECT ?, 0
+                UNION ALL
+                SELECT (
+                    SELECT c.id FROM chat_messages c
+                    WHERE c.parent_message_id = d.id
+                    ORDER BY c.created_at DESC
+                    LIMIT 1
+                ), d.depth + 1
+                FROM descend d
+                WHERE d.id IS NOT NULL
+            )
+            SELECT id FROM descend WHERE id IS NOT NULL ORDER BY depth DESC LIMIT 1
+            "#,
+        )
+        .bind(message_id)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(leaf)
+    }
+
+    pub async fn set_active_leaf(
+        &self,
+        conversation_id: &str,
+        message_id: &str,
+    ) -> Result<(), sqlx::Error> {
+        let now = Utc::now().to_rfc3339();
+
+        sqlx::query("UPDATE conversations SET active_leaf_id = ?, updated_at = ? WHERE id = ?")
+            .bind(message_id)
+            .bind(&now)
+            .bind(conversation_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
     pub async fn create_document(
         &self,
         document_id: &str,
@@ -194,6 +578,45 @@ impl ChatDatabase {
         Ok(())
     }
 
+    /// Id of the document's Files API upload, if it has one
+    pub async fn get_document_file_id(&self, document_id: &str) -> Result<Option<String>, sqlx::Error> {
+        let file_id: Option<Option<String>> =
+            sqlx::query_scalar("SELECT file_id FROM documents WHERE id = ?")
+                .bind(document_id)
+                .fetch_optional(&self.pool)
+                .await?;
+
+        Ok(file_id.flatten())
+    }
+
+    pub async fn set_document_file_id(&self, document_id: &str, file_id: &str) -> Result<(), sqlx::Error> {
+        let now = Utc::now().to_rfc3339();
+
+        sqlx::query("UPDATE documents SET file_id = ?, updated_at = ? WHERE id = ?")
+            .bind(file_id)
+            .bind(&now)
+            .bind(document_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Forget a Files API upload that no longer exists, returning the document it was of
+    pub async fn clear_document_file_id(&self, file_id: &str) -> Result<Option<String>, sqlx::Error> {
+        let now = Utc::now().to_rfc3339();
+
+        let document_id: Option<String> = sqlx::query_scalar(
+            "UPDATE documents SET file_id = NULL, updated_at = ? WHERE file_id = ? RETURNING id",
+        )
+        .bind(&now)
+        .bind(file_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(document_id)
+    }
+
     pub async fn list_recent_documents(&self, limit: i32) -> Result<Vec<Document>, sqlx::Error> {
         let documents: Vec<Document> = sqlx::query_as(
             r#"
@@ -210,22 +633,47 @@ impl ChatDatabase {
         Ok(documents)
     }
 
+    /// Find documents whose filename, keywords or topics contain `query`, newest first
+    pub async fn search_documents(&self, query: &str, limit: i32) -> Result<Vec<Document>, sqlx::Error> {
+        // `%` and `_` in the query are matched literally
+        let escaped = query.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+        let pattern = format!("%{}%", escaped);
+
+        let documents: Vec<Document> = sqlx::query_as(
+            r#"
+            SELECT id, filename, keywords, topics, uploaded_at, created_at, updated_at
+            FROM documents
+            WHERE filename LIKE ?1 ESCAPE '\' OR keywords LIKE ?1 ESCAPE '\' OR topics LIKE ?1 ESCAPE '\'
+            ORDER BY uploaded_at DESC
+            LIMIT ?2
+            "#,
+        )
+        .bind(&pattern)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(documents)
+    }
+
     // ===== Multiple Chats Support =====
 
     pub async fn create_conversation(
         &self,
         document_id: &str,
         title: Option<&str>,
+        preset_id: Option<&str>,
     ) -> Result<String, sqlx::Error> {
         let conversation_id = Uuid::new_v4().to_string();
         let now = Utc::now().to_rfc3339();
 
         sqlx::query(
-            "INSERT INTO conversations (id, document_id, title, created_at, updated_at) VALUES (?, ?, ?, ?, ?)",
+            "INSERT INTO conversations (id, document_id, title, preset_id, created_at, updated_at) VALUES (?, ?, ?, ?, ?, ?)",
         )
         .bind(&conversation_id)
         .bind(document_id)
         .bind(title)
+        .bind(preset_id)
         .bind(&now)
         .bind(&now)
         .execute(&self.pool)
@@ -238,14 +686,15 @@ impl ChatDatabase {
         &self,
         document_id: &str,
     ) -> Result<Vec<Conversation>, sqlx::Error> {
-        let conversations: Vec<Conversation> = sqlx::query_as(
+        let conversations: Vec<Conversation> = sqlx::query_as(&format!(
             r#"
-            SELECT id, document_id, title, created_at, updated_at
+            SELECT {}
             FROM conversations
             WHERE document_id = ?
             ORDER BY updated_at DESC
             "#,
-        )
+            CONVERSATION_COLUMNS
+        ))
         .bind(document_id)
         .fetch_all(&self.pool)
         .await?;
@@ -257,9 +706,10 @@ impl ChatDatabase {
         &self,
         conversation_id: &str,
     ) -> Result<Option<Conversation>, sqlx::Error> {
-        let conversation: Option<Conversation> = sqlx::query_as(
-            "SELECT id, document_id, title, created_at, updated_at FROM conversations WHERE id = ?",
-        )
+        let conversation: Option<Conversation> = sqlx::query_as(&format!(
+            "SELECT {} FROM conversations WHERE id = ?",
+            CONVERSATION_COLUMNS
+        ))
         .bind(conversation_id)
         .fetch_optional(&self.pool)
         .await?;
@@ -267,19 +717,56 @@ impl ChatDatabase {
         Ok(conversation)
     }
 
+    /// Delete a conversation with its messages, feedback, document links and chat turns.
+    ///
+    /// Usage records are kept, with their conversation id, so spending history still adds up.
     pub async fn delete_conversation(&self, conversation_id: &str) -> Result<(), sqlx::Error> {
-        // Delete all messages first (foreign key constraint)
+        // All or nothing, so search never finds messages of a half-deleted conversation
+        let mut tx = self.pool.begin().await?;
+
+        // Delete feedback, messages and document links first (foreign key constraint)
+        sqlx::query(
+            r#"
+            DELETE FROM message_feedback
+            WHERE message_id IN (SELECT id FROM chat_messages WHERE conversation_id = ?)
+            "#,
+        )
+        .bind(conversation_id)
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            r#"
+            DELETE FROM chat_messages_fts
+            WHERE message_id IN (SELECT id FROM chat_messages WHERE conversation_id = ?)
+            "#,
+        )
+        .bind(conversation_id)
+        .execute(&mut *tx)
+        .await?;
+
         sqlx::query("DELETE FROM chat_messages WHERE conversation_id = ?")
             .bind(conversation_id)
-            .execute(&self.pool)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query("DELETE FROM conversation_documents WHERE conversation_id = ?")
+            .bind(conversation_id)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query("DELETE FROM chat_turns WHERE conversation_id = ?")
+            .bind(conversation_id)
+            .execute(&mut *tx)
             .await?;
 
         // Delete the conversation
         sqlx::query("DELETE FROM conversations WHERE id = ?")
             .bind(conversation_id)
-            .execute(&self.pool)
+            .execute(&mut *tx)
             .await?;
 
+        tx.commit().await?;
         Ok(())
     }
 
@@ -300,22 +787,758 @@ impl ChatDatabase {
         Ok(())
     }
 
-    pub async fn get_conversation_messages_by_id(
+    pub async fn set_conversation_preset(
         &self,
         conversation_id: &str,
-    ) -> Result<Vec<StoredMessage>, sqlx::Error> {
-        let messages: Vec<StoredMessage> = sqlx::query_as(
-            r#"
-            SELECT id, role, content, created_at
-            FROM chat_messages
-            WHERE conversation_id = ?
-            ORDER BY created_at ASC
-            "#,
-        )
-        .bind(conversation_id)
-        .fetch_all(&self.pool)
-        .await?;
+        preset_id: Option<&str>,
+    ) -> Result<(), sqlx::Error> {
+        let now = Utc::now().to_rfc3339();
 
-        Ok(messages)
+        sqlx::query("UPDATE conversations SET preset_id = ?, updated_at = ? WHERE id = ?")
+            .bind(preset_id)
+            .bind(&now)
+            .bind(conversation_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn set_summarization_enabled(
+        &self,
+        conversation_id: &str,
+        enabled: bool,
+    ) -> Result<(), sqlx::Error> {
+        let now = Utc::now().to_rfc3339();
+
+        sqlx::query("UPDATE conversations SET summarization_enabled = ?, updated_at = ? WHERE id = ?")
+            .bind(enabled)
+            .bind(&now)
+            .bind(conversation_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Store the rolling summary and the last message it covers
+    pub async fn update_conversation_summary(
+        &self,
+        conversation_id: &str,
+        summary: &str,
+        summarized_until: &str,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE conversations SET summary = ?, summarized_until = ? WHERE id = ?")
+            .bind(summary)
+            .bind(summarized_until)
+            .bind(conversation_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Set a generated title, unless the conversation has been titled in the meantime
+    pub async fn set_title_if_unset(
+        &self,
+        conversation_id: &str,
+        title: &str,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("UPDATE conversations SET title = ? WHERE id = ? AND title IS NULL")
+            .bind(title)
+            .bind(conversation_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Get the messages on a conversation's active branch, oldest first
+    pub async fn get_conversation_messages_by_id(
+        &self,
+        conversation_id: &str,
+    ) -> Result<Vec<StoredMessage>, sqlx::Error> {
+        let leaf: Option<(Option<String>,)> =
+            sqlx::query_as("SELECT active_leaf_id FROM conversations WHERE id = ?")
+                .bind(conversation_id)
+                .fetch_optional(&self.pool)
+                .await?;
+
+        match leaf {
+            Some((Some(leaf),)) => self.get_message_path(&leaf).await,
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    /// Record that a conversation covers the given documents
+    pub async fn add_conversation_documents(
+        &self,
+        conversation_id: &str,
+        document_ids: &[String],
+    ) -> Result<(), sqlx::Error> {
+        let now = Utc::now().to_rfc3339();
+
+        for document_id in document_ids {
+            sqlx::query(
+                "INSERT OR IGNORE INTO conversation_documents (conversation_id, document_id, added_at) VALUES (?, ?, ?)",
+            )
+            .bind(conversation_id)
+            .bind(document_id)
+            .bind(&now)
+            .execute(&self.pool)
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// List the documents a conversation covers, in the order they were added
+    pub async fn list_conversation_documents(
+        &self,
+        conversation_id: &str,
+    ) -> Result<Vec<String>, sqlx::Error> {
+        let rows: Vec<(String,)> = sqlx::query_as(
+            r#"
+            SELECT document_id
+            FROM conversation_documents
+            WHERE conversation_id = ?
+            ORDER BY added_at ASC, rowid ASC
+            "#,
+        )
+        .bind(conversation_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|(id,)| id).collect())
+    }
+
+    // ===== Search =====
+
+    /// Search message content with an FTS5 `query`, best matches first.
+    ///
+    /// Matched terms in the snippet are wrapped in `start_mark` and `end_mark`.
+    pub async fn search_messages(
+        &self,
+        query: &str,
+        document_id: Option<&str>,
+        (start_mark, end_mark): (&str, &str),
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<MessageSearchHit>, sqlx::Error> {
+        let hits: Vec<MessageSearchHit> = sqlx::query_as(
+            r#"
+            SELECT
+                m.id AS message_id,
+                m.role,
+                m.conversation_id,
+                c.title AS conversation_title,
+                c.document_id,
+                d.filename AS document_filename,
+                snippet(chat_messages_fts, 1, ?1, ?2, '…', 16) AS snippet,
+                m.created_at
+            FROM chat_messages_fts
+            JOIN chat_messages m ON m.id = chat_messages_fts.message_id
+            JOIN conversations c ON c.id = m.conversation_id
+            LEFT JOIN documents d ON d.id = c.document_id
+            WHERE chat_messages_fts MATCH ?3
+              AND (?4 IS NULL OR c.document_id = ?4)
+            ORDER BY rank
+            LIMIT ?5 OFFSET ?6
+            "#,
+        )
+        .bind(start_mark)
+        .bind(end_mark)
+        .bind(query)
+        .bind(document_id)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(hits)
+    }
+
+    // ===== Document Artifacts =====
+
+    /// Store a new version of an action's result, numbered one past the latest, and return it.
+    ///
+    /// The number is picked in the same statement as the insert, so concurrent saves
+    /// cannot claim the same version.
+    pub async fn save_artifact(
+        &self,
+        document_id: &str,
+        action: &str,
+        content: &str,
+        model: &str,
+    ) -> Result<DocumentArtifact, sqlx::Error> {
+        let artifact_id = Uuid::new_v4().to_string();
+        let now = Utc::now().to_rfc3339();
+
+        let artifact: DocumentArtifact = sqlx::query_as(&format!(
+            r#"
+            INSERT INTO document_artifacts (id, document_id, action, version, content, model, created_at)
+            SELECT ?1, ?2, ?3, COALESCE(MAX(version), 0) + 1, ?4, ?5, ?6
+            FROM document_artifacts
+            WHERE document_id = ?2 AND action = ?3
+            RETURNING {}
+            "#,
+            ARTIFACT_COLUMNS
+        ))
+        .bind(&artifact_id)
+        .bind(document_id)
+        .bind(action)
+        .bind(content)
+        .bind(model)
+        .bind(&now)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(artifact)
+    }
+
+    pub async fn latest_artifact(
+        &self,
+        document_id: &str,
+        action: &str,
+    ) -> Result<Option<DocumentArtifact>, sqlx::Error> {
+        let artifact: Option<DocumentArtifact> = sqlx::query_as(&format!(
+            r#"
+            SELECT {}
+            FROM document_artifacts
+            WHERE document_id = ? AND action = ?
+            ORDER BY version DESC
+            LIMIT 1
+            "#,
+            ARTIFACT_COLUMNS
+        ))
+        .bind(document_id)
+        .bind(action)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(artifact)
+    }
+
+    /// List every version of a document's artifacts, optionally for one action,
+    /// newest first
+    pub async fn list_artifacts(
+        &self,
+        document_id: &str,
+        action: Option<&str>,
+    ) -> Result<Vec<DocumentArtifact>, sqlx::Error> {
+        let artifacts: Vec<DocumentArtifact> = sqlx::query_as(&format!(
+            r#"
+            SELECT {}
+            FROM document_artifacts
+            WHERE document_id = ?1 AND (?2 IS NULL OR action = ?2)
+            ORDER BY created_at DESC, version DESC
+            "#,
+            ARTIFACT_COLUMNS
+        ))
+        .bind(document_id)
+        .bind(action)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(artifacts)
+    }
+
+    // ===== Prompt Presets =====
+
+    pub async fn create_preset(
+        &self,
+        name: &str,
+        description: Option<&str>,
+        system_prompt: &str,
+    ) -> Result<String, sqlx::Error> {
+        let preset_id = Uuid::new_v4().to_string();
+        let now = Utc::now().to_rfc3339();
+
+        sqlx::query(
+            r#"
+            INSERT INTO prompt_presets (id, name, description, system_prompt, created_at, updated_at)
+            VALUES (?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&preset_id)
+        .bind(name)
+        .bind(description)
+        .bind(system_prompt)
+        .bind(&now)
+        .bind(&now)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(preset_id)
+    }
+
+    pub async fn list_presets(&self) -> Result<Vec<PromptPreset>, sqlx::Error> {
+        let presets: Vec<PromptPreset> = sqlx::query_as(&format!(
+            "SELECT {} FROM prompt_presets ORDER BY name ASC",
+            PRESET_COLUMNS
+        ))
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(presets)
+    }
+
+    pub async fn get_preset(&self, preset_id: &str) -> Result<Option<PromptPreset>, sqlx::Error> {
+        let preset: Option<PromptPreset> = sqlx::query_as(&format!(
+            "SELECT {} FROM prompt_presets WHERE id = ?",
+            PRESET_COLUMNS
+        ))
+        .bind(preset_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(preset)
+    }
+
+    /// Update the given fields of a preset; `None` leaves a field unchanged
+    pub async fn update_preset(
+        &self,
+        preset_id: &str,
+        name: Option<&str>,
+        description: Option<&str>,
+        system_prompt: Option<&str>,
+    ) -> Result<(), sqlx::Error> {
+        let now = Utc::now().to_rfc3339();
+
+        sqlx::query(
+            r#"
+            UPDATE prompt_presets
+            SET name = COALESCE(?, name),
+                description = COALESCE(?, description),
+                system_prompt = COALESCE(?, system_prompt),
+                updated_at = ?
+            WHERE id = ?
+            "#,
+        )
+        .bind(name)
+        .bind(description)
+        .bind(system_prompt)
+        .bind(&now)
+        .bind(preset_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Delete a preset; conversations using it fall back to the default prompt
+    pub async fn delete_preset(&self, preset_id: &str) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE conversations SET preset_id = NULL WHERE preset_id = ?")
+            .bind(preset_id)
+            .execute(&self.pool)
+            .await?;
+
+        sqlx::query("DELETE FROM prompt_presets WHERE id = ?")
+            .bind(preset_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    // ===== Chat Turns =====
+
+    /// Record a new pending turn, returning its id, or `None` if a turn already uses
+    /// `idempotency_key`
+    pub async fn create_chat_turn(
+        &self,
+        idempotency_key: Option<&str>,
+        request_hash: &str,
+        document_id: &str,
+        conversation_id: Option<&str>,
+    ) -> Result<Option<String>, sqlx::Error> {
+        let now = Utc::now().to_rfc3339();
+
+        let turn_id: Option<(String,)> = sqlx::query_as(
+            r#"
+            INSERT INTO chat_turns (id, idempotency_key, request_hash, document_id, conversation_id, status, created_at, updated_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT(idempotency_key) DO NOTHING
+            RETURNING id
+            "#,
+        )
+        .bind(Uuid::new_v4().to_string())
+        .bind(idempotency_key)
+        .bind(request_hash)
+        .bind(document_id)
+        .bind(conversation_id)
+        .bind(TurnStatus::Pending)
+        .bind(&now)
+        .bind(&now)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(turn_id.map(|(id,)| id))
+    }
+
+    pub async fn get_chat_turn_by_key(
+        &self,
+        idempotency_key: &str,
+    ) -> Result<Option<ChatTurn>, sqlx::Error> {
+        let turn: Option<ChatTurn> = sqlx::query_as(&format!(
+            "SELECT {} FROM chat_turns WHERE idempotency_key = ?",
+            TURN_COLUMNS
+        ))
+        .bind(idempotency_key)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(turn)
+    }
+
+    /// Mark a failed or cancelled turn as pending again so it can be retried.
+    /// Returns false if it is no longer in either state
+    pub async fn restart_chat_turn(&self, turn_id: &str) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(
+            r#"
+            UPDATE chat_turns
+            SET status = ?, response = NULL, error = NULL, updated_at = ?
+            WHERE id = ? AND status IN (?, ?)
+            "#,
+        )
+        .bind(TurnStatus::Pending)
+        .bind(Utc::now().to_rfc3339())
+        .bind(turn_id)
+        .bind(TurnStatus::Failed)
+        .bind(TurnStatus::Cancelled)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Note the conversation and pending assistant message a turn fills in, so a retry
+    /// of the turn can fill in the same message
+    pub async fn set_chat_turn_reply(
+        &self,
+        turn_id: &str,
+        conversation_id: &str,
+        message_id: &str,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "UPDATE chat_turns SET conversation_id = ?, message_id = ?, updated_at = ? WHERE id = ?",
+        )
+        .bind(conversation_id)
+        .bind(message_id)
+        .bind(Utc::now().to_rfc3339())
+        .bind(turn_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Record how a pending turn ended. Turns that already ended are left alone
+    pub async fn finish_chat_turn(
+        &self,
+        turn_id: &str,
+        status: TurnStatus,
+        conversation_id: Option<&str>,
+        response: Option<&ChatApiResponse>,
+        error: Option<&str>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            UPDATE chat_turns
+            SET status = ?,
+                conversation_id = COALESCE(?, conversation_id),
+                response = ?,
+                error = ?,
+                updated_at = ?
+            WHERE id = ? AND status = ?
+            "#,
+        )
+        .bind(status)
+        .bind(conversation_id)
+        .bind(response.map(Json))
+        .bind(error)
+        .bind(Utc::now().to_rfc3339())
+        .bind(turn_id)
+        .bind(TurnStatus::Pending)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    // ===== Feedback =====
+
+    /// Rate a message, replacing any earlier rating of it
+    pub async fn set_feedback(
+        &self,
+        message_id: &str,
+        rating: Rating,
+        category: Option<FeedbackCategory>,
+        comment: Option<&str>,
+    ) -> Result<Feedback, sqlx::Error> {
+        let now = Utc::now().to_rfc3339();
+
+        let feedback: Feedback = sqlx::query_as(
+            r#"
+            INSERT INTO message_feedback (message_id, rating, category, comment, created_at, updated_at)
+            VALUES (?, ?, ?, ?, ?, ?)
+            ON CONFLICT(message_id) DO UPDATE SET
+                rating = excluded.rating,
+                category = excluded.category,
+                comment = excluded.comment,
+                updated_at = excluded.updated_at
+            RETURNING message_id, rating, category, comment, created_at, updated_at
+            "#,
+        )
+        .bind(message_id)
+        .bind(rating)
+        .bind(category)
+        .bind(comment)
+        .bind(&now)
+        .bind(&now)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(feedback)
+    }
+
+    pub async fn get_feedback(&self, message_id: &str) -> Result<Option<Feedback>, sqlx::Error> {
+        let feedback: Option<Feedback> = sqlx::query_as(
+            r#"
+            SELECT message_id, rating, category, comment, created_at, updated_at
+            FROM message_feedback
+            WHERE message_id = ?
+            "#,
+        )
+        .bind(message_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(feedback)
+    }
+
+    pub async fn delete_feedback(&self, message_id: &str) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM message_feedback WHERE message_id = ?")
+            .bind(message_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// List rated answers with their questions, oldest rating first, optionally
+    /// filtered by rating, category and document
+    pub async fn list_rated_exchanges(
+        &self,
+        rating: Option<Rating>,
+        category: Option<FeedbackCategory>,
+        document_id: Option<&str>,
+    ) -> Result<Vec<RatedExchange>, sqlx::Error> {
+        let exchanges: Vec<RatedExchange> = sqlx::query_as(
+            r#"
+            SELECT
+                answer.id AS message_id,
+                answer.conversation_id,
+                c.document_id,
+                question.content AS question,
+                answer.content AS answer,
+                answer.citations,
+                answer.quotes,
+                answer.model,
+                f.rating,
+                f.category,
+                f.comment,
+                f.updated_at AS rated_at
+            FROM message_feedback f
+            JOIN chat_messages answer ON answer.id = f.message_id
+            JOIN conversations c ON c.id = answer.conversation_id
+            LEFT JOIN chat_messages question
+                ON question.id = answer.parent_message_id AND question.role = 'user'
+            WHERE (?1 IS NULL OR f.rating = ?1)
+              AND (?2 IS NULL OR f.category = ?2)
+              AND (?3 IS NULL OR c.document_id = ?3)
+            ORDER BY f.updated_at ASC
+            "#,
+        )
+        .bind(rating)
+        .bind(category)
+        .bind(document_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(exchanges)
+    }
+
+    // ===== Usage Tracking =====
+
+    pub async fn record_usage(&self, record: NewUsageRecord<'_>) -> Result<(), sqlx::Error> {
+        let id = Uuid::new_v4().to_string();
+        let now = Utc::now().to_rfc3339();
+
+        sqlx::query(
+            r#"
+            INSERT INTO usage_records (
+                id, kind, conversation_id, document_id, message_id, model,
+                input_tokens, output_tokens, cache_creation_input_tokens, cache_read_input_tokens,
+                cost_usd, uncached_cost_usd, created_at
+            )
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&id)
+        .bind(record.kind)
+        .bind(record.conversation_id)
+        .bind(record.document_id)
+        .bind(record.message_id)
+        .bind(record.model)
+        .bind(record.input_tokens)
+        .bind(record.output_tokens)
+        .bind(record.cache_creation_input_tokens)
+        .bind(record.cache_read_input_tokens)
+        .bind(record.cost_usd)
+        .bind(record.uncached_cost_usd)
+        .bind(&now)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn document_usage(&self, document_id: &str) -> Result<UsageSummary, sqlx::Error> {
+        let summary: UsageSummary = sqlx::query_as(&format!(
+            "SELECT {} FROM usage_records WHERE document_id = ?",
+            USAGE_SUMMARY_COLUMNS
+        ))
+        .bind(document_id)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(summary)
+    }
+
+    pub async fn conversation_usage(&self, conversation_id: &str) -> Result<UsageSummary, sqlx::Error> {
+        let summary: UsageSummary = sqlx::query_as(&format!(
+            "SELECT {} FROM usage_records WHERE conversation_id = ?",
+            USAGE_SUMMARY_COLUMNS
+        ))
+        .bind(conversation_id)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(summary)
+    }
+
+    /// Usage per day, most recent first, for days on or after `since` (YYYY-MM-DD)
+    pub async fn daily_usage(&self, since: &str) -> Result<Vec<DailyUsage>, sqlx::Error> {
+        let days: Vec<DailyUsage> = sqlx::query_as(&format!(
+            r#"
+            SELECT substr(created_at, 1, 10) AS day, {}
+            FROM usage_records
+            WHERE substr(created_at, 1, 10) >= ?
+            GROUP BY day
+            ORDER BY day DESC
+            "#,
+            USAGE_SUMMARY_COLUMNS
+        ))
+        .bind(since)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(days)
+    }
+}
+
+const CONVERSATION_COLUMNS: &str = "id, document_id, title, summary, summarized_until, \
+    summarization_enabled, active_leaf_id, preset_id, created_at, updated_at";
+
+const ARTIFACT_COLUMNS: &str = "id, document_id, action, version, content, model, created_at";
+
+const PRESET_COLUMNS: &str = "id, name, description, system_prompt, created_at, updated_at";
+
+const TURN_COLUMNS: &str = "id, idempotency_key, request_hash, document_id, conversation_id, \
+    message_id, status, response, error, created_at, updated_at";
+
+const MESSAGE_COLUMNS: &str = "chat_messages.id, parent_message_id, role, content, citations, \
+    quotes, model, tool_calls, selection, status, error, thinking, chat_messages.created_at";
+
+const USAGE_SUMMARY_COLUMNS: &str = r#"
+    COUNT(*) AS calls,
+    COALESCE(SUM(input_tokens), 0) AS input_tokens,
+    COALESCE(SUM(output_tokens), 0) AS output_tokens,
+    COALESCE(SUM(cache_creation_input_tokens), 0) AS cache_creation_input_tokens,
+    COALESCE(SUM(cache_read_input_tokens), 0) AS cache_read_input_tokens,
+    COALESCE(SUM(cost_usd), 0.0) AS cost_usd,
+    COALESCE(SUM(uncached_cost_usd), 0.0) AS uncached_cost_usd
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::initialize_database;
+
+    /// A fresh in-memory database, which every pooled connection shares
+    async fn test_db() -> ChatDatabase {
+        let pool = initialize_database("sqlite::memory:").await.unwrap();
+        ChatDatabase::new(pool)
+    }
+
+    #[tokio::test]
+    async fn search_matches_wildcards_literally() {
+        let db = test_db().await;
+        db.create_document("percent", "100%_done.pdf").await.unwrap();
+        db.create_document("plain", "1000 done.pdf").await.unwrap();
+
+        let found = db.search_documents("100%_", 10).await.unwrap();
+        let ids: Vec<&str> = found.iter().map(|d| d.id.as_str()).collect();
+        assert_eq!(ids, ["percent"]);
+
+        assert!(db.search_documents("\\", 10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn concurrent_artifact_saves_get_distinct_versions() {
+        let db = test_db().await;
+        db.create_document("doc", "paper.pdf").await.unwrap();
+
+        let saves = (0..8).map(|i| {
+            let db = db.clone();
+            tokio::spawn(async move {
+                db.save_artifact("doc", "summary", &format!("Summary {i}"), "model")
+                    .await
+                    .unwrap()
+                    .version
+            })
+        });
+        let mut versions = Vec::new();
+        for save in saves.collect::<Vec<_>>() {
+            versions.push(save.await.unwrap());
+        }
+        versions.sort();
+        assert_eq!(versions, (1..=8).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn deleted_conversations_leave_nothing_searchable() {
+        let db = test_db().await;
+        let conversation_id = db.get_or_create_conversation("doc").await.unwrap();
+        db.save_message(NewMessage {
+            conversation_id: &conversation_id,
+            parent_message_id: None,
+            role: "user",
+            content: "What about spectrum auctions?",
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        db.create_chat_turn(Some("turn-key"), "hash", "doc", Some(&conversation_id))
+            .await
+            .unwrap();
+        let search = || db.search_messages("spectrum", None, ("[", "]"), 10, 0);
+        assert_eq!(search().await.unwrap().len(), 1);
+
+        db.delete_conversation(&conversation_id).await.unwrap();
+        assert!(search().await.unwrap().is_empty());
+        assert!(db.get_conversation(&conversation_id).await.unwrap().is_none());
+        assert!(db.get_chat_turn_by_key("turn-key").await.unwrap().is_none());
     }
 }
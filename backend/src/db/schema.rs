@@ -1,6 +1,28 @@
 use sqlx::sqlite::{SqlitePool, SqlitePoolOptions};
 use sqlx::Result;
 
+/// Presets available in a new database, as (id, name, description, system prompt)
+const BUILTIN_PRESETS: [(&str, &str, &str, &str); 3] = [
+    (
+        "newcomer",
+        "Explain for a newcomer",
+        "Plain-language explanations for readers new to the field",
+        "You are an AI assistant helping someone who is new to the subject understand a document. Explain concepts in plain language, define jargon and acronyms the first time they appear, and use short examples or analogies where they help. Point out which parts of the document are worth reading first.",
+    ),
+    (
+        "peer-reviewer",
+        "Peer reviewer",
+        "Critical review of claims, methods and novelty",
+        "You are an AI assistant acting as a rigorous but fair peer reviewer of a document. Assess whether the claims are supported by the evidence presented, whether the methods are sound and reproducible, and how the work relates to what it cites. Call out missing baselines, unclear definitions and overstated conclusions, and note genuine strengths as well.",
+    ),
+    (
+        "skeptical-statistician",
+        "Skeptical statistician",
+        "Scrutiny of data, statistics and experimental design",
+        "You are an AI assistant acting as a skeptical statistician reading a document. Scrutinize sample sizes, study design, statistical tests, effect sizes, confidence intervals and multiple comparisons. Flag p-hacking risks, confounders, selection bias and conclusions that go beyond what the data show.",
+    ),
+];
+
 pub async fn initialize_database(database_url: &str) -> Result<SqlitePool> {
     let pool = SqlitePoolOptions::new()
         .max_connections(5)
@@ -30,6 +52,11 @@ pub async fn initialize_database(database_url: &str) -> Result<SqlitePool> {
             id TEXT PRIMARY KEY,
             document_id TEXT NOT NULL,
             title TEXT,
+            summary TEXT,
+            summarized_until TEXT,
+            summarization_enabled INTEGER NOT NULL DEFAULT 1,
+            active_leaf_id TEXT,
+            preset_id TEXT,
             created_at TEXT NOT NULL,
             updated_at TEXT NOT NULL,
             FOREIGN KEY (document_id) REFERENCES documents(id)
@@ -49,6 +76,21 @@ pub async fn initialize_database(database_url: &str) -> Result<SqlitePool> {
     .await
     .ok(); // Ignore error if column already exists
 
+    // Add the Files API upload column if it doesn't exist (for existing databases)
+    sqlx::query("ALTER TABLE documents ADD COLUMN file_id TEXT")
+        .execute(&pool)
+        .await
+        .ok(); // Ignore error if column already exists
+
+    // Add rolling summary columns if they don't exist (for existing databases)
+    for statement in [
+        "ALTER TABLE conversations ADD COLUMN summary TEXT",
+        "ALTER TABLE conversations ADD COLUMN summarized_until TEXT",
+        "ALTER TABLE conversations ADD COLUMN summarization_enabled INTEGER NOT NULL DEFAULT 1",
+    ] {
+        sqlx::query(statement).execute(&pool).await.ok(); // Ignore error if column already exists
+    }
+
     sqlx::query(
         r#"
         CREATE TABLE IF NOT EXISTS chat_messages (
@@ -56,6 +98,15 @@ pub async fn initialize_database(database_url: &str) -> Result<SqlitePool> {
             conversation_id TEXT NOT NULL,
             role TEXT NOT NULL,
             content TEXT NOT NULL,
+            parent_message_id TEXT,
+            citations TEXT,
+            quotes TEXT,
+            model TEXT,
+            tool_calls TEXT,
+            selection TEXT,
+            status TEXT NOT NULL DEFAULT 'complete',
+            error TEXT,
+            thinking TEXT,
             created_at TEXT NOT NULL,
             FOREIGN KEY (conversation_id) REFERENCES conversations(id)
         )
@@ -64,6 +115,327 @@ pub async fn initialize_database(database_url: &str) -> Result<SqlitePool> {
     .execute(&pool)
     .await?;
 
+    // Add citation columns if they don't exist (for existing databases)
+    sqlx::query(
+        r#"
+        ALTER TABLE chat_messages ADD COLUMN citations TEXT
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .ok(); // Ignore error if column already exists
+
+    sqlx::query(
+        r#"
+        ALTER TABLE chat_messages ADD COLUMN quotes TEXT
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .ok(); // Ignore error if column already exists
+
+    // Add model column if it doesn't exist (for existing databases)
+    sqlx::query(
+        r#"
+        ALTER TABLE chat_messages ADD COLUMN model TEXT
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .ok(); // Ignore error if column already exists
+
+    // Add tool call column if it doesn't exist (for existing databases)
+    sqlx::query(
+        r#"
+        ALTER TABLE chat_messages ADD COLUMN tool_calls TEXT
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .ok(); // Ignore error if column already exists
+
+    // Add selection column if it doesn't exist (for existing databases)
+    sqlx::query(
+        r#"
+        ALTER TABLE chat_messages ADD COLUMN selection TEXT
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .ok(); // Ignore error if column already exists
+
+    // Add message status columns if they don't exist (for existing databases)
+    sqlx::query(
+        r#"
+        ALTER TABLE chat_messages ADD COLUMN status TEXT NOT NULL DEFAULT 'complete'
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .ok(); // Ignore error if column already exists
+
+    sqlx::query(
+        r#"
+        ALTER TABLE chat_messages ADD COLUMN error TEXT
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .ok(); // Ignore error if column already exists
+
+    // Add reasoning column if it doesn't exist (for existing databases)
+    sqlx::query(
+        r#"
+        ALTER TABLE chat_messages ADD COLUMN thinking TEXT
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .ok(); // Ignore error if column already exists
+
+    // Replies still pending at startup were interrupted by a restart
+    sqlx::query(
+        r#"
+        UPDATE chat_messages
+        SET status = 'failed', error = 'Interrupted by a server restart'
+        WHERE status = 'pending'
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    // Add message tree columns if they don't exist (for existing databases). Messages
+    // were previously a flat list, so each one becomes the child of the one before it
+    let added_parent = sqlx::query("ALTER TABLE chat_messages ADD COLUMN parent_message_id TEXT")
+        .execute(&pool)
+        .await
+        .is_ok();
+    if added_parent {
+        sqlx::query(
+            r#"
+            UPDATE chat_messages
+            SET parent_message_id = (
+                SELECT prev.id FROM chat_messages prev
+                WHERE prev.conversation_id = chat_messages.conversation_id
+                  AND prev.created_at < chat_messages.created_at
+                ORDER BY prev.created_at DESC
+                LIMIT 1
+            )
+            "#,
+        )
+        .execute(&pool)
+        .await?;
+    }
+
+    let added_leaf = sqlx::query("ALTER TABLE conversations ADD COLUMN active_leaf_id TEXT")
+        .execute(&pool)
+        .await
+        .is_ok();
+    if added_leaf {
+        sqlx::query(
+            r#"
+            UPDATE conversations
+            SET active_leaf_id = (
+                SELECT m.id FROM chat_messages m
+                WHERE m.conversation_id = conversations.id
+                ORDER BY m.created_at DESC
+                LIMIT 1
+            )
+            "#,
+        )
+        .execute(&pool)
+        .await?;
+    }
+
+    // Add prompt preset column if it doesn't exist (for existing databases)
+    sqlx::query(
+        r#"
+        ALTER TABLE conversations ADD COLUMN preset_id TEXT
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .ok(); // Ignore error if column already exists
+
+    // Built-in presets are only seeded when the table is first created, so deleting
+    // one sticks
+    let (has_presets,): (bool,) = sqlx::query_as(
+        "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = 'prompt_presets'",
+    )
+    .fetch_one(&pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS prompt_presets (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            description TEXT,
+            system_prompt TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            updated_at TEXT NOT NULL
+        )
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    if !has_presets {
+        let now = chrono::Utc::now().to_rfc3339();
+        for (id, name, description, system_prompt) in BUILTIN_PRESETS {
+            sqlx::query(
+                r#"
+                INSERT INTO prompt_presets (id, name, description, system_prompt, created_at, updated_at)
+                VALUES (?, ?, ?, ?, ?, ?)
+                "#,
+            )
+            .bind(id)
+            .bind(name)
+            .bind(description)
+            .bind(system_prompt)
+            .bind(&now)
+            .bind(&now)
+            .execute(&pool)
+            .await?;
+        }
+    }
+
+    // Full-text index over message content. Existing messages are indexed when the
+    // table is first created; after that `save_message` keeps it in sync
+    let (has_message_index,): (bool,) = sqlx::query_as(
+        "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = 'chat_messages_fts'",
+    )
+    .fetch_one(&pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE VIRTUAL TABLE IF NOT EXISTS chat_messages_fts USING fts5(
+            message_id UNINDEXED,
+            content,
+            tokenize = 'porter unicode61 remove_diacritics 2'
+        )
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    if !has_message_index {
+        sqlx::query("INSERT INTO chat_messages_fts (message_id, content) SELECT id, content FROM chat_messages")
+            .execute(&pool)
+            .await?;
+    }
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS conversation_documents (
+            conversation_id TEXT NOT NULL,
+            document_id TEXT NOT NULL,
+            added_at TEXT NOT NULL,
+            PRIMARY KEY (conversation_id, document_id),
+            FOREIGN KEY (conversation_id) REFERENCES conversations(id),
+            FOREIGN KEY (document_id) REFERENCES documents(id)
+        )
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS message_feedback (
+            message_id TEXT PRIMARY KEY,
+            rating TEXT NOT NULL,
+            category TEXT,
+            comment TEXT,
+            created_at TEXT NOT NULL,
+            updated_at TEXT NOT NULL,
+            FOREIGN KEY (message_id) REFERENCES chat_messages(id)
+        )
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS document_artifacts (
+            id TEXT PRIMARY KEY,
+            document_id TEXT NOT NULL,
+            action TEXT NOT NULL,
+            version INTEGER NOT NULL,
+            content TEXT NOT NULL,
+            model TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            UNIQUE (document_id, action, version),
+            FOREIGN KEY (document_id) REFERENCES documents(id)
+        )
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS usage_records (
+            id TEXT PRIMARY KEY,
+            kind TEXT NOT NULL,
+            conversation_id TEXT,
+            document_id TEXT,
+            message_id TEXT,
+            model TEXT NOT NULL,
+            input_tokens INTEGER NOT NULL,
+            output_tokens INTEGER NOT NULL,
+            cache_creation_input_tokens INTEGER NOT NULL DEFAULT 0,
+            cache_read_input_tokens INTEGER NOT NULL DEFAULT 0,
+            cost_usd REAL NOT NULL,
+            uncached_cost_usd REAL NOT NULL,
+            created_at TEXT NOT NULL
+        )
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    // One row per chat request. Requests sent with an Idempotency-Key store their
+    // response here so a retry can be answered without calling Claude again
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS chat_turns (
+            id TEXT PRIMARY KEY,
+            idempotency_key TEXT UNIQUE,
+            request_hash TEXT NOT NULL,
+            document_id TEXT NOT NULL,
+            conversation_id TEXT,
+            status TEXT NOT NULL,
+            response TEXT,
+            error TEXT,
+            created_at TEXT NOT NULL,
+            updated_at TEXT NOT NULL
+        )
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    // Add the column naming a turn's assistant reply if it doesn't exist (for existing databases)
+    sqlx::query("ALTER TABLE chat_turns ADD COLUMN message_id TEXT")
+        .execute(&pool)
+        .await
+        .ok(); // Ignore error if column already exists
+
+    // Turns still pending at startup were interrupted by a restart
+    sqlx::query(
+        r#"
+        UPDATE chat_turns
+        SET status = 'cancelled', updated_at = ?
+        WHERE status = 'pending'
+        "#,
+    )
+    .bind(chrono::Utc::now().to_rfc3339())
+    .execute(&pool)
+    .await?;
+
     // Create indexes for faster queries
     sqlx::query(
         r#"
@@ -92,5 +464,41 @@ pub async fn initialize_database(database_url: &str) -> Result<SqlitePool> {
     .execute(&pool)
     .await?;
 
+    sqlx::query(
+        r#"
+        CREATE INDEX IF NOT EXISTS idx_messages_parent_message_id
+        ON chat_messages(parent_message_id)
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE INDEX IF NOT EXISTS idx_feedback_rating
+        ON message_feedback(rating)
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE INDEX IF NOT EXISTS idx_usage_document_id
+        ON usage_records(document_id)
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE INDEX IF NOT EXISTS idx_usage_conversation_id
+        ON usage_records(conversation_id)
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
     Ok(pool)
 }
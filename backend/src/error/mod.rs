@@ -21,12 +21,13 @@ pub enum ApiError {
     // Client errors (4xx)
     BadRequest(String),
     NotFound(String),
+    Conflict(String),
 
     // Server errors (5xx)
     InternalError(String),
     DatabaseError(String),
     StorageError(String),
-    ExternalApiError(String),
+    UpstreamError(String),
 }
 
 impl fmt::Display for ApiError {
@@ -34,10 +35,11 @@ impl fmt::Display for ApiError {
         match self {
             ApiError::BadRequest(msg) => write!(f, "Bad request: {}", msg),
             ApiError::NotFound(msg) => write!(f, "Not found: {}", msg),
+            ApiError::Conflict(msg) => write!(f, "Conflict: {}", msg),
             ApiError::InternalError(msg) => write!(f, "Internal error: {}", msg),
             ApiError::DatabaseError(msg) => write!(f, "Database error: {}", msg),
             ApiError::StorageError(msg) => write!(f, "Storage error: {}", msg),
-            ApiError::ExternalApiError(msg) => write!(f, "External API error: {}", msg),
+            ApiError::UpstreamError(msg) => write!(f, "Upstream error: {}", msg),
         }
     }
 }
@@ -50,10 +52,11 @@ impl ApiError {
         match self {
             ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
             ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
             ApiError::InternalError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             ApiError::DatabaseError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             ApiError::StorageError(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            ApiError::ExternalApiError(_) => StatusCode::BAD_GATEWAY,
+            ApiError::UpstreamError(_) => StatusCode::BAD_GATEWAY,
         }
     }
 
@@ -62,10 +65,11 @@ impl ApiError {
         match self {
             ApiError::BadRequest(_) => "BAD_REQUEST",
             ApiError::NotFound(_) => "NOT_FOUND",
+            ApiError::Conflict(_) => "CONFLICT",
             ApiError::InternalError(_) => "INTERNAL_ERROR",
             ApiError::DatabaseError(_) => "DATABASE_ERROR",
             ApiError::StorageError(_) => "STORAGE_ERROR",
-            ApiError::ExternalApiError(_) => "EXTERNAL_API_ERROR",
+            ApiError::UpstreamError(_) => "UPSTREAM_ERROR",
         }
     }
 
@@ -74,10 +78,11 @@ impl ApiError {
         match self {
             ApiError::BadRequest(msg)
             | ApiError::NotFound(msg)
+            | ApiError::Conflict(msg)
             | ApiError::InternalError(msg)
             | ApiError::DatabaseError(msg)
             | ApiError::StorageError(msg)
-            | ApiError::ExternalApiError(msg) => msg,
+            | ApiError::UpstreamError(msg) => msg,
         }
     }
 
@@ -85,14 +90,14 @@ impl ApiError {
     fn log(&self) {
         match self {
             // Client errors - log as warnings
-            ApiError::BadRequest(_) | ApiError::NotFound(_) => {
+            ApiError::BadRequest(_) | ApiError::NotFound(_) | ApiError::Conflict(_) => {
                 eprintln!("[WARN] {}", self);
             }
             // Server errors - log as errors
             ApiError::InternalError(_)
             | ApiError::DatabaseError(_)
             | ApiError::StorageError(_)
-            | ApiError::ExternalApiError(_) => {
+            | ApiError::UpstreamError(_) => {
                 eprintln!("[ERROR] {}", self);
             }
         }
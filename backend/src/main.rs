@@ -1,12 +1,28 @@
 mod api;
 mod claude;
+mod config;
 mod db;
 mod error;
 mod models;
 mod storage;
 
-use crate::api::{backfill_metadata, backfill_metadata_handler, chat_handler, get_chat_history_handler, get_document_handler, list_documents_handler, upload_handler, AppState};
+use crate::api::{
+    activate_branch_handler, backfill_metadata, backfill_metadata_handler, chat_handler,
+    chat_stream_handler, conversation_usage_handler, create_conversation_handler,
+    create_preset_handler, daily_usage_handler, delete_conversation_handler,
+    delete_feedback_handler, delete_preset_handler, document_usage_handler, edit_message_handler,
+    export_conversation_handler, export_feedback_handler, get_chat_history_handler,
+    get_conversation_handler, get_document_handler, get_feedback_handler, get_preset_handler,
+    list_artifacts_handler, list_branches_handler, list_conversations_handler,
+    list_documents_handler, list_presets_handler, regenerate_message_handler,
+    regenerate_title_handler, retry_message_handler, run_action_handler, search_messages_handler,
+    set_feedback_handler, update_conversation_handler, update_preset_handler, upload_handler,
+    AppState,
+};
 use crate::claude::ClaudeClient;
+use crate::config::{
+    ClaudeEndpoint, FilesApiPolicy, HistoryPolicy, ModelPolicy, PriceTable, RetryPolicy,
+};
 use crate::db::{initialize_database, ChatDatabase};
 use crate::storage::{FileStorage, LocalStorage};
 use axum::{routing::*, Router};
@@ -36,10 +52,31 @@ async fn main() {
         .time_to_live(Duration::from_secs(3600))
         .build();
 
+    // Remember which Files API uploads were recently confirmed to still exist
+    let files_policy = FilesApiPolicy::from_env();
+    let file_ids = Cache::builder()
+        .max_capacity(1000)
+        .time_to_live(files_policy.recheck_after)
+        .build();
+
+    let model_policy = ModelPolicy::from_env();
+    let mut endpoint = ClaudeEndpoint::from_env();
+    files_policy.configure(&mut endpoint);
+
     let state = Arc::new(AppState {
-        claude: ClaudeClient::new(api_key),
+        claude: ClaudeClient::new(
+            api_key,
+            model_policy.default_model.clone(),
+            endpoint,
+            RetryPolicy::from_env(),
+        ),
+        model_policy,
+        pricing: PriceTable::from_env(),
+        history_policy: HistoryPolicy::from_env(),
         storage: storage.clone(),
         pdf_cache,
+        files_policy,
+        file_ids,
         chat_db,
     });
 
@@ -63,10 +100,66 @@ async fn main() {
     let app = Router::new()
         .route("/api/upload", post(upload_handler))
         .route("/api/chat", post(chat_handler))
+        .route("/api/chat/stream", post(chat_stream_handler))
         .route("/api/chat/history/:document_id", get(get_chat_history_handler))
         .route("/api/documents", get(list_documents_handler))
         .route("/api/documents/:id", get(get_document_handler))
+        .route("/api/documents/:id/actions/:action", post(run_action_handler))
+        .route("/api/documents/:id/artifacts", get(list_artifacts_handler))
+        .route(
+            "/api/documents/:id/conversations",
+            get(list_conversations_handler).post(create_conversation_handler),
+        )
+        .route(
+            "/api/documents/:id/conversations/:conversation_id",
+            get(get_conversation_handler)
+                .patch(update_conversation_handler)
+                .delete(delete_conversation_handler),
+        )
+        .route(
+            "/api/documents/:id/conversations/:conversation_id/title",
+            post(regenerate_title_handler),
+        )
+        .route(
+            "/api/documents/:id/conversations/:conversation_id/messages/:message_id/regenerate",
+            post(regenerate_message_handler),
+        )
+        .route(
+            "/api/documents/:id/conversations/:conversation_id/messages/:message_id/edit",
+            post(edit_message_handler),
+        )
+        .route(
+            "/api/documents/:id/conversations/:conversation_id/messages/:message_id/retry",
+            post(retry_message_handler),
+        )
+        .route(
+            "/api/documents/:id/conversations/:conversation_id/messages/:message_id/branches",
+            get(list_branches_handler),
+        )
+        .route(
+            "/api/documents/:id/conversations/:conversation_id/messages/:message_id/activate",
+            post(activate_branch_handler),
+        )
+        .route(
+            "/api/documents/:id/conversations/:conversation_id/messages/:message_id/feedback",
+            get(get_feedback_handler)
+                .put(set_feedback_handler)
+                .delete(delete_feedback_handler),
+        )
+        .route("/api/feedback/export", get(export_feedback_handler))
+        .route("/api/conversations/:id/export", get(export_conversation_handler))
+        .route("/api/presets", get(list_presets_handler).post(create_preset_handler))
+        .route(
+            "/api/presets/:id",
+            get(get_preset_handler)
+                .patch(update_preset_handler)
+                .delete(delete_preset_handler),
+        )
+        .route("/api/search/messages", get(search_messages_handler))
         .route("/api/metadata/backfill", post(backfill_metadata_handler))
+        .route("/api/usage/documents/:id", get(document_usage_handler))
+        .route("/api/usage/conversations/:id", get(conversation_usage_handler))
+        .route("/api/usage/daily", get(daily_usage_handler))
         .with_state(state)
         .layer(CorsLayer::permissive());
 
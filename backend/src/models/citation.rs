@@ -0,0 +1,171 @@
+use crate::claude::TextCitation;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::sync::LazyLock;
+
+/// A page reference found in an assistant answer, e.g. "(page 3, page 7)"
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Citation {
+    /// Document filename, for multi-document answers written as "(filename, page X)"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub document: Option<String>,
+    pub pages: Vec<u32>,
+    /// Character offsets of the whole reference in the answer, end exclusive
+    pub start: usize,
+    pub end: usize,
+}
+
+/// A passage Claude quoted from a document to support part of its answer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DocumentQuote {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub document_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub document_title: Option<String>,
+    pub cited_text: String,
+    /// Pages the quote spans, both inclusive
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_page: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end_page: Option<u32>,
+    /// Character offsets of the supported text in the answer, end exclusive
+    pub start: usize,
+    pub end: usize,
+}
+
+impl DocumentQuote {
+    /// Build a quote from a Claude citation, given the ids of the documents in prompt order
+    pub fn from_citation(
+        citation: TextCitation,
+        document_ids: &[String],
+        start: usize,
+        end: usize,
+    ) -> Self {
+        let start_page = citation.start_page_number;
+        let end_page = citation
+            .end_page_number
+            .map(|end| end.saturating_sub(1).max(start_page.unwrap_or(end)));
+
+        Self {
+            document_id: document_ids.get(citation.document_index).cloned(),
+            document_title: citation.document_title,
+            cited_text: citation.cited_text,
+            start_page,
+            end_page,
+            start,
+            end,
+        }
+    }
+}
+
+static PAGE_REFERENCE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)\(([^()]*\bpages?\s+\d+[^()]*)\)").unwrap());
+
+/// Start of the page list: the first "page N" at the start or right after a comma
+static FIRST_PAGE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)(?:^|,)\s*pages?\s+\d").unwrap());
+
+static PAGE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)^pages?\s+(\d+)(?:\s*[-–]\s*(\d+))?$").unwrap());
+
+/// Longest "page X-Y" range expanded into individual pages
+const MAX_PAGE_RANGE: u32 = 50;
+
+/// Parse the "(page X)" references that `GUIDELINES_PROMPT` asks Claude to write
+pub fn parse_page_citations(text: &str) -> Vec<Citation> {
+    PAGE_REFERENCE
+        .captures_iter(text)
+        .filter_map(|caps| {
+            let whole = caps.get(0)?;
+            let inner = caps.get(1)?.as_str();
+
+            // An optional leading document name, which may itself contain commas, then
+            // one or more pages
+            let first_page = FIRST_PAGE.find(inner)?;
+            let document = inner[..first_page.start()].trim();
+            let page_list = inner[first_page.start()..].trim_start_matches(',');
+
+            let mut pages = Vec::new();
+            for part in page_list.split(',').map(str::trim) {
+                pages.extend(parse_pages(part)?);
+            }
+
+            Some(Citation {
+                document: (!document.is_empty()).then(|| document.to_string()),
+                pages,
+                start: text[..whole.start()].chars().count(),
+                end: text[..whole.end()].chars().count(),
+            })
+        })
+        .collect()
+}
+
+/// Pages named by "page X" or "page X-Y"
+fn parse_pages(part: &str) -> Option<Vec<u32>> {
+    let caps = PAGE.captures(part)?;
+    let first: u32 = caps[1].parse().ok()?;
+    let last: u32 = match caps.get(2) {
+        Some(last) => last.as_str().parse().ok()?,
+        None => first,
+    };
+
+    if last < first || last - first >= MAX_PAGE_RANGE {
+        return None;
+    }
+    Some((first..=last).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pages(text: &str) -> Vec<Vec<u32>> {
+        parse_page_citations(text).into_iter().map(|c| c.pages).collect()
+    }
+
+    #[test]
+    fn parses_single_and_multiple_pages() {
+        assert_eq!(pages("See (page 3)."), vec![vec![3]]);
+        assert_eq!(pages("See (page 3, page 7) and (Page 9)."), vec![vec![3, 7], vec![9]]);
+    }
+
+    #[test]
+    fn expands_page_ranges() {
+        assert_eq!(pages("(pages 3-5)"), vec![vec![3, 4, 5]]);
+        assert_eq!(pages("(page 2, page 4–5)"), vec![vec![2, 4, 5]]);
+        assert!(pages("(page 5-3)").is_empty());
+        assert!(pages("(page 1-500)").is_empty());
+    }
+
+    #[test]
+    fn parses_named_documents() {
+        let citations = parse_page_citations("As shown (report.pdf, page 2, page 4).");
+        assert_eq!(citations.len(), 1);
+        assert_eq!(citations[0].document.as_deref(), Some("report.pdf"));
+        assert_eq!(citations[0].pages, vec![2, 4]);
+    }
+
+    #[test]
+    fn keeps_commas_in_document_names() {
+        let citations = parse_page_citations("(Smith, Jones 2020.pdf, page 3)");
+        assert_eq!(citations[0].document.as_deref(), Some("Smith, Jones 2020.pdf"));
+        assert_eq!(citations[0].pages, vec![3]);
+    }
+
+    #[test]
+    fn ignores_parentheses_that_are_not_page_references() {
+        assert!(pages("(see above) and (page three)").is_empty());
+        assert!(pages("(page 3, chapter 2)").is_empty());
+    }
+
+    #[test]
+    fn offsets_count_characters_not_bytes() {
+        let text = "Lösung für Größe (page 12)";
+        let citation = &parse_page_citations(text)[0];
+        assert_eq!(citation.start, 17);
+        assert_eq!(citation.end, 26);
+        let chars: Vec<char> = text.chars().collect();
+        let reference: String = chars[citation.start..citation.end].iter().collect();
+        assert_eq!(reference, "(page 12)");
+    }
+}
@@ -0,0 +1,28 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum Rating {
+    Up,
+    Down,
+}
+
+/// What was wrong with a poorly rated answer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum FeedbackCategory {
+    WrongPage,
+    Hallucinated,
+    Unhelpful,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FeedbackRequest {
+    pub rating: Rating,
+    #[serde(default)]
+    pub category: Option<FeedbackCategory>,
+    #[serde(default)]
+    pub comment: Option<String>,
+}
@@ -1,3 +1,9 @@
 pub mod chat;
+pub mod citation;
+pub mod feedback;
+pub mod tool;
 
 pub use chat::*;
+pub use citation::*;
+pub use feedback::*;
+pub use tool::*;
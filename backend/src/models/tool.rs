@@ -0,0 +1,13 @@
+use serde::{Deserialize, Serialize};
+
+/// A tool Claude called while answering, and what the backend returned
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub id: String,
+    pub name: String,
+    pub input: serde_json::Value,
+    /// Text result sent back to Claude, or the error message
+    pub output: String,
+    #[serde(default)]
+    pub is_error: bool,
+}
@@ -15,7 +15,6 @@ impl LocalStorage {
         let base_path = base_path.as_ref().to_path_buf();
         std::fs::create_dir_all(&base_path)?;
         std::fs::create_dir_all(base_path.join("pdfs"))?;
-        std::fs::create_dir_all(base_path.join("metadata"))?;
 
         Ok(Self { base_path })
     }
@@ -23,10 +22,6 @@ impl LocalStorage {
     fn pdf_path(&self, document_id: &str) -> PathBuf {
         self.base_path.join("pdfs").join(format!("{}.pdf", document_id))
     }
-
-    fn metadata_path(&self, document_id: &str) -> PathBuf {
-        self.base_path.join("metadata").join(format!("{}.json", document_id))
-    }
 }
 
 #[async_trait]
@@ -65,44 +60,8 @@ impl FileStorage for LocalStorage {
         Ok(self.pdf_path(document_id).exists())
     }
 
-    async fn delete(&self, document_id: &str) -> StorageResult<()> {
-        let pdf_path = self.pdf_path(document_id);
-        let metadata_path = self.metadata_path(document_id);
-
-        if pdf_path.exists() {
-            fs::remove_file(pdf_path).await?;
-        }
-
-        if metadata_path.exists() {
-            fs::remove_file(metadata_path).await?;
-        }
-
-        Ok(())
-    }
-
     async fn get_pdf_base64(&self, document_id: &str) -> StorageResult<String> {
         let data = self.get_pdf(document_id).await?;
         Ok(BASE64.encode(&data))
     }
-
-    async fn store_metadata(&self, document_id: &str, metadata: &[u8]) -> StorageResult<()> {
-        let path = self.metadata_path(document_id);
-        let mut file = fs::File::create(&path).await?;
-        file.write_all(metadata).await?;
-        Ok(())
-    }
-
-    async fn get_metadata(&self, document_id: &str) -> StorageResult<Option<Vec<u8>>> {
-        let path = self.metadata_path(document_id);
-
-        if !path.exists() {
-            return Ok(None);
-        }
-
-        let mut file = fs::File::open(&path).await?;
-        let mut buffer = Vec::new();
-        file.read_to_end(&mut buffer).await?;
-
-        Ok(Some(buffer))
-    }
 }
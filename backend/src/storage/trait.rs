@@ -11,9 +11,6 @@ pub enum StorageError {
 
     #[error("Invalid file format")]
     InvalidFormat,
-
-    #[error("Storage error: {0}")]
-    Other(String),
 }
 
 pub type StorageResult<T> = Result<T, StorageError>;
@@ -29,15 +26,6 @@ pub trait FileStorage: Send + Sync {
     /// Check if a document exists
     async fn exists(&self, document_id: &str) -> StorageResult<bool>;
 
-    /// Delete a document
-    async fn delete(&self, document_id: &str) -> StorageResult<()>;
-
     /// Get the base64 encoded PDF (for Claude API)
     async fn get_pdf_base64(&self, document_id: &str) -> StorageResult<String>;
-
-    /// Store metadata (for caching conversation state)
-    async fn store_metadata(&self, document_id: &str, metadata: &[u8]) -> StorageResult<()>;
-
-    /// Get metadata
-    async fn get_metadata(&self, document_id: &str) -> StorageResult<Option<Vec<u8>>>;
 }
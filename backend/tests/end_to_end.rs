@@ -0,0 +1,592 @@
+//! End-to-end tests running the backend binary against a local mock of the Claude API
+
+mod support;
+
+use serde_json::{json, Value};
+use std::time::Duration;
+use support::mock_claude::{file_ids, request_text, MockClaude, MockReply, DEFAULT_REPLY};
+use support::{Backend, TINY_PDF};
+
+const METADATA_REPLY: &str = r#"{"keywords": ["broadband", "coverage"], "topics": ["networks"]}"#;
+
+/// Answer metadata extraction with keywords and everything else with the canned reply
+fn metadata_responder(body: &Value) -> MockReply {
+    if request_text(body).contains("Extract keywords and topics") {
+        MockReply::text(METADATA_REPLY)
+    } else {
+        MockReply::text(DEFAULT_REPLY)
+    }
+}
+
+/// Number of requests the mock received for the chat turn asking `question`.
+///
+/// Title generation quotes the question too, but is sent without a system prompt.
+fn chat_requests(mock: &MockClaude, question: &str) -> usize {
+    mock.requests()
+        .iter()
+        .filter(|r| !r.body["system"].is_null() && r.last_text().contains(question))
+        .count()
+}
+
+fn has_keywords(document: &Value) -> bool {
+    document["keywords"].as_array().is_some_and(|k| !k.is_empty())
+}
+
+#[tokio::test]
+async fn upload_extracts_metadata_and_chat_answers() {
+    let mock = MockClaude::start().await;
+    mock.respond_with(metadata_responder);
+    let backend = Backend::start(&mock).await;
+
+    let document_id = backend.upload("report.pdf", TINY_PDF).await;
+    let document = backend.wait_for_document(&document_id, has_keywords).await;
+    assert_eq!(document["keywords"], json!(["broadband", "coverage"]));
+    assert_eq!(document["topics"], json!(["networks"]));
+
+    let response = backend
+        .post_json(
+            "/chat",
+            &json!({ "document_id": document_id, "message": "What does it cover?" }),
+        )
+        .await;
+    assert!(response.status().is_success());
+    let reply: Value = response.json().await.unwrap();
+    assert_eq!(reply["response"], DEFAULT_REPLY);
+
+    let chat = mock
+        .requests()
+        .into_iter()
+        .find(|r| r.last_text().contains("What does it cover?"))
+        .expect("Chat request never reached Claude");
+    assert_eq!(chat.header("x-api-key"), Some("test-key"));
+    assert_eq!(chat.header("anthropic-version"), Some("2023-06-01"));
+    assert_eq!(chat.header("anthropic-beta"), Some("prompt-caching-2024-07-31"));
+}
+
+#[tokio::test]
+async fn stream_relays_the_mock_reply() {
+    let mock = MockClaude::start().await;
+    mock.respond_with(metadata_responder);
+    let backend = Backend::start(&mock).await;
+    let document_id = backend.upload("report.pdf", TINY_PDF).await;
+
+    let events = backend
+        .post_json(
+            "/chat/stream",
+            &json!({ "document_id": document_id, "message": "Summarize it" }),
+        )
+        .await
+        .text()
+        .await
+        .unwrap();
+
+    assert!(events.contains("event: delta"), "No delta event in {events}");
+    assert!(events.contains("canned reply"));
+    assert!(events.contains("event: message"));
+}
+
+#[tokio::test]
+async fn endpoint_settings_come_from_the_environment() {
+    let mock = MockClaude::start().await;
+    let backend = Backend::start_with_env(
+        &mock,
+        &[("ANTHROPIC_VERSION", "2099-01-01"), ("ANTHROPIC_BETA", "")],
+    )
+    .await;
+
+    backend.upload("report.pdf", TINY_PDF).await;
+    let requests = mock.wait_for_requests(1).await;
+
+    assert_eq!(requests[0].header("anthropic-version"), Some("2099-01-01"));
+    assert_eq!(requests[0].header("anthropic-beta"), None);
+}
+
+#[tokio::test]
+async fn backfill_fills_in_metadata_that_failed_on_upload() {
+    let mock = MockClaude::start().await;
+    mock.respond_with(metadata_responder);
+    mock.fail_next(1, 400, "invalid_request_error");
+    let backend = Backend::start(&mock).await;
+
+    let document_id = backend.upload("report.pdf", TINY_PDF).await;
+    mock.wait_for_requests(1).await;
+
+    let response = backend.post_json("/metadata/backfill", &json!({})).await;
+    assert!(response.status().is_success());
+    let result: Value = response.json().await.unwrap();
+    assert_eq!(result["processed"], 1);
+    assert_eq!(result["succeeded"], 1);
+
+    let document = backend.wait_for_document(&document_id, has_keywords).await;
+    assert_eq!(document["topics"], json!(["networks"]));
+}
+
+#[tokio::test]
+async fn upstream_error_fails_the_reply_until_retried() {
+    let mock = MockClaude::start().await;
+    mock.respond_with(metadata_responder);
+    let backend = Backend::start(&mock).await;
+    let document_id = backend.upload("report.pdf", TINY_PDF).await;
+    backend.wait_for_document(&document_id, has_keywords).await;
+
+    mock.fail_next(1, 400, "invalid_request_error");
+    let response = backend
+        .post_json(
+            "/chat",
+            &json!({ "document_id": document_id, "message": "Will this work?" }),
+        )
+        .await;
+    assert_eq!(response.status(), 502);
+    assert_eq!(chat_requests(&mock, "Will this work?"), 1, "Validation errors must not be retried");
+
+    let conversations = backend
+        .get_json(&format!("/documents/{document_id}/conversations"))
+        .await;
+    let conversation_id = conversations[0]["id"].as_str().unwrap().to_string();
+    let conversation_path = format!("/documents/{document_id}/conversations/{conversation_id}");
+    let conversation = backend.get_json(&conversation_path).await;
+    let failed = conversation["messages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|m| m["role"] == "assistant")
+        .unwrap();
+    assert_eq!(failed["status"], "failed");
+
+    let message_id = failed["id"].as_str().unwrap();
+    let response = backend
+        .post_json(&format!("{conversation_path}/messages/{message_id}/retry"), &json!({}))
+        .await;
+    assert!(response.status().is_success());
+    let reply: Value = response.json().await.unwrap();
+    assert_eq!(reply["response"], DEFAULT_REPLY);
+    assert_eq!(reply["message_id"], message_id);
+}
+
+#[tokio::test]
+async fn slow_reply_rejects_a_concurrent_request_with_the_same_key() {
+    let mock = MockClaude::start().await;
+    mock.respond_with(metadata_responder);
+    let backend = Backend::start(&mock).await;
+    let document_id = backend.upload("report.pdf", TINY_PDF).await;
+    backend.wait_for_document(&document_id, has_keywords).await;
+
+    mock.set_latency(Duration::from_millis(500));
+    let body = json!({ "document_id": document_id, "message": "Take your time" });
+    let send = || {
+        backend
+            .http
+            .post(backend.api("/chat"))
+            .header("Idempotency-Key", "slow-turn")
+            .json(&body)
+            .send()
+    };
+
+    let first = send();
+    let second = async {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        send().await
+    };
+    let (first, second) = tokio::join!(first, second);
+
+    assert!(first.unwrap().status().is_success());
+    assert_eq!(second.unwrap().status(), 409);
+}
+
+#[tokio::test]
+async fn retried_idempotent_turn_reuses_the_failed_reply() {
+    let mock = MockClaude::start().await;
+    mock.respond_with(metadata_responder);
+    let backend = Backend::start(&mock).await;
+    let document_id = backend.upload("report.pdf", TINY_PDF).await;
+    backend.wait_for_document(&document_id, has_keywords).await;
+
+    let body = json!({ "document_id": document_id, "message": "Second time lucky?" });
+    let send = || {
+        backend
+            .http
+            .post(backend.api("/chat"))
+            .header("Idempotency-Key", "retried-turn")
+            .json(&body)
+            .send()
+    };
+
+    mock.fail_next(1, 400, "invalid_request_error");
+    assert_eq!(send().await.unwrap().status(), 502);
+    let response = send().await.unwrap();
+    assert!(response.status().is_success());
+    let reply: Value = response.json().await.unwrap();
+    assert_eq!(reply["response"], DEFAULT_REPLY);
+
+    let conversation_id = reply["conversation_id"].as_str().unwrap();
+    let conversation = backend
+        .get_json(&format!("/documents/{document_id}/conversations/{conversation_id}"))
+        .await;
+    let messages = conversation["messages"].as_array().unwrap();
+    let roles: Vec<&str> = messages.iter().map(|m| m["role"].as_str().unwrap()).collect();
+    assert_eq!(roles, ["user", "assistant"]);
+    assert_eq!(messages[1]["id"], reply["message_id"]);
+    assert_eq!(messages[1]["status"], "complete");
+}
+
+#[tokio::test]
+async fn regenerate_abandoned_by_the_client_fails_the_reply() {
+    let mock = MockClaude::start().await;
+    mock.respond_with(metadata_responder);
+    let backend = Backend::start(&mock).await;
+    let document_id = backend.upload("report.pdf", TINY_PDF).await;
+    backend.wait_for_document(&document_id, has_keywords).await;
+
+    let response = backend
+        .post_json("/chat", &json!({ "document_id": document_id, "message": "Try that again" }))
+        .await;
+    let reply: Value = response.json().await.unwrap();
+    let conversation_id = reply["conversation_id"].as_str().unwrap();
+    let conversation_path = format!("/documents/{document_id}/conversations/{conversation_id}");
+    let answer_id = reply["message_id"].as_str().unwrap();
+
+    mock.set_latency(Duration::from_millis(1000));
+    let abandoned = backend
+        .http
+        .post(backend.api(&format!("{conversation_path}/messages/{answer_id}/regenerate")))
+        .json(&json!({}))
+        .timeout(Duration::from_millis(200))
+        .send()
+        .await;
+    assert!(abandoned.unwrap_err().is_timeout());
+
+    let mut regenerated = Value::Null;
+    for _ in 0..50 {
+        let conversation = backend.get_json(&conversation_path).await;
+        regenerated = conversation["messages"][1].clone();
+        if regenerated["id"] != reply["message_id"] && regenerated["status"] == "failed" {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_ne!(regenerated["id"], reply["message_id"]);
+    assert_eq!(regenerated["status"], "failed", "An abandoned reply must be left retryable");
+}
+
+#[tokio::test]
+async fn thinking_from_every_tool_round_is_returned() {
+    let mock = MockClaude::start().await;
+    mock.respond_with(metadata_responder);
+    let backend = Backend::start(&mock).await;
+    let document_id = backend.upload("report.pdf", TINY_PDF).await;
+    backend.wait_for_document(&document_id, has_keywords).await;
+
+    let message = |content: Value, stop_reason: &str| {
+        MockReply::Message(json!({
+            "id": "msg_thinking",
+            "type": "message",
+            "role": "assistant",
+            "model": "claude-mock",
+            "content": content,
+            "stop_reason": stop_reason,
+            "usage": { "input_tokens": 10, "output_tokens": 5 }
+        }))
+    };
+    mock.push_reply(message(
+        json!([
+            { "type": "thinking", "thinking": "Check the library first.", "signature": "sig-1" },
+            { "type": "tool_use", "id": "toolu_1", "name": "search_library", "input": { "query": "coverage" } }
+        ]),
+        "tool_use",
+    ));
+    mock.push_reply(message(
+        json!([
+            { "type": "thinking", "thinking": "Only this report matches.", "signature": "sig-2" },
+            { "type": "text", "text": DEFAULT_REPLY }
+        ]),
+        "end_turn",
+    ));
+
+    let response = backend
+        .post_json(
+            "/chat",
+            &json!({
+                "document_id": document_id,
+                "message": "Anything else on coverage?",
+                "thinking_budget": 2048,
+                "include_thinking": true
+            }),
+        )
+        .await;
+    assert!(response.status().is_success());
+    let reply: Value = response.json().await.unwrap();
+    assert_eq!(reply["tool_calls"][0]["name"], "search_library");
+    assert_eq!(reply["thinking"], "Check the library first.\n\nOnly this report matches.");
+    assert_eq!(reply["response"], DEFAULT_REPLY);
+}
+
+#[tokio::test]
+async fn answer_cut_off_by_max_tokens_is_continued() {
+    let mock = MockClaude::start().await;
+    mock.respond_with(metadata_responder);
+    let backend = Backend::start(&mock).await;
+    let document_id = backend.upload("report.pdf", TINY_PDF).await;
+    backend.wait_for_document(&document_id, has_keywords).await;
+
+    mock.push_reply(MockReply::Message(json!({
+        "id": "msg_cut_off",
+        "type": "message",
+        "role": "assistant",
+        "model": "claude-mock",
+        "content": [{ "type": "text", "text": "Coverage grew in rural areas " }],
+        "stop_reason": "max_tokens",
+        "usage": { "input_tokens": 10, "output_tokens": 5 }
+    })));
+    mock.push_reply(MockReply::text(" and along highways (page 1)."));
+
+    let response = backend
+        .post_json(
+            "/chat",
+            &json!({ "document_id": document_id, "message": "Where did coverage grow?" }),
+        )
+        .await;
+    assert!(response.status().is_success());
+    let reply: Value = response.json().await.unwrap();
+    assert_eq!(reply["response"], "Coverage grew in rural areas and along highways (page 1).");
+    assert_eq!(reply["stop_reason"], "end_turn");
+    assert!(reply["truncated"].is_null());
+
+    // The partial answer goes back, without its trailing space, as the start of the reply
+    let continuation = mock
+        .requests()
+        .into_iter()
+        .filter(|r| !r.body["system"].is_null())
+        .last()
+        .unwrap();
+    assert_eq!(continuation.body["messages"].as_array().unwrap().last().unwrap()["role"], "assistant");
+    assert_eq!(continuation.last_text(), "Coverage grew in rural areas");
+}
+
+#[tokio::test]
+async fn continued_answer_can_call_a_tool_and_be_continued_again() {
+    let mock = MockClaude::start().await;
+    mock.respond_with(metadata_responder);
+    let backend = Backend::start(&mock).await;
+    let document_id = backend.upload("report.pdf", TINY_PDF).await;
+    backend.wait_for_document(&document_id, has_keywords).await;
+
+    let message = |content: Value, stop_reason: &str| {
+        MockReply::Message(json!({
+            "id": "msg_continued",
+            "type": "message",
+            "role": "assistant",
+            "model": "claude-mock",
+            "content": content,
+            "stop_reason": stop_reason,
+            "usage": { "input_tokens": 10, "output_tokens": 5 }
+        }))
+    };
+    mock.push_reply(message(json!([{ "type": "text", "text": "Coverage grew " }]), "max_tokens"));
+    mock.push_reply(message(
+        json!([
+            { "type": "text", "text": " in rural areas." },
+            { "type": "tool_use", "id": "toolu_1", "name": "search_library", "input": { "query": "coverage" } }
+        ]),
+        "tool_use",
+    ));
+    mock.push_reply(message(json!([{ "type": "text", "text": " No other report " }]), "max_tokens"));
+    mock.push_reply(MockReply::text(" covers it."));
+
+    let response = backend
+        .post_json(
+            "/chat",
+            &json!({ "document_id": document_id, "message": "Where else did coverage grow?" }),
+        )
+        .await;
+    assert!(response.status().is_success());
+    let reply: Value = response.json().await.unwrap();
+    assert_eq!(reply["tool_calls"][0]["name"], "search_library");
+    assert_eq!(reply["response"], "Coverage grew in rural areas. No other report covers it.");
+    assert_eq!(reply["stop_reason"], "end_turn");
+
+    // Every turn alternates roles, and the last continuation only carries its own partial
+    let continuation = mock
+        .requests()
+        .into_iter()
+        .filter(|r| !r.body["system"].is_null())
+        .last()
+        .unwrap();
+    let messages = continuation.body["messages"].as_array().unwrap();
+    let roles: Vec<&str> = messages.iter().map(|m| m["role"].as_str().unwrap()).collect();
+    assert!(roles.windows(2).all(|pair| pair[0] != pair[1]), "Roles must alternate: {roles:?}");
+    assert_eq!(roles[roles.len() - 2..], ["user", "assistant"]);
+    assert_eq!(continuation.last_text(), " No other report");
+}
+
+#[tokio::test]
+async fn overload_and_server_errors_are_retried() {
+    let mock = MockClaude::start().await;
+    mock.respond_with(metadata_responder);
+    let backend = Backend::start(&mock).await;
+    let document_id = backend.upload("report.pdf", TINY_PDF).await;
+    backend.wait_for_document(&document_id, has_keywords).await;
+
+    mock.fail_next(1, 529, "overloaded_error");
+    mock.fail_next(1, 500, "api_error");
+    let response = backend
+        .post_json(
+            "/chat",
+            &json!({ "document_id": document_id, "message": "Are you busy?" }),
+        )
+        .await;
+
+    assert!(response.status().is_success());
+    assert_eq!(chat_requests(&mock, "Are you busy?"), 3);
+}
+
+#[tokio::test]
+async fn rate_limit_waits_for_retry_after() {
+    let mock = MockClaude::start().await;
+    mock.respond_with(metadata_responder);
+    let backend = Backend::start(&mock).await;
+    let document_id = backend.upload("report.pdf", TINY_PDF).await;
+    backend.wait_for_document(&document_id, has_keywords).await;
+
+    mock.push_reply(MockReply::rate_limited(1));
+    let started = std::time::Instant::now();
+    let response = backend
+        .post_json(
+            "/chat",
+            &json!({ "document_id": document_id, "message": "Too fast?" }),
+        )
+        .await;
+
+    assert!(response.status().is_success());
+    assert!(started.elapsed() >= Duration::from_secs(1));
+    assert_eq!(chat_requests(&mock, "Too fast?"), 2);
+}
+
+#[tokio::test]
+async fn retries_give_up_after_the_configured_limit() {
+    let mock = MockClaude::start().await;
+    mock.respond_with(metadata_responder);
+    let backend = Backend::start_with_env(&mock, &[("CLAUDE_MAX_RETRIES", "1")]).await;
+    let document_id = backend.upload("report.pdf", TINY_PDF).await;
+    backend.wait_for_document(&document_id, has_keywords).await;
+
+    mock.fail_next(2, 503, "api_error");
+    let response = backend
+        .post_json(
+            "/chat",
+            &json!({ "document_id": document_id, "message": "Still down?" }),
+        )
+        .await;
+
+    assert_eq!(response.status(), 502);
+    assert_eq!(chat_requests(&mock, "Still down?"), 2);
+}
+
+#[tokio::test]
+async fn files_api_mode_uploads_once_and_replaces_expired_files() {
+    let mock = MockClaude::start().await;
+    mock.respond_with(metadata_responder);
+    let backend = Backend::start_with_env(
+        &mock,
+        &[("CLAUDE_FILES_API", "true"), ("CLAUDE_FILES_RECHECK_SECS", "0")],
+    )
+    .await;
+
+    let document_id = backend.upload("report.pdf", TINY_PDF).await;
+    backend.wait_for_document(&document_id, has_keywords).await;
+    assert_eq!(mock.file_uploads(), 1);
+
+    let ask = |question: &'static str| {
+        let body = json!({ "document_id": document_id, "message": question });
+        let backend = &backend;
+        async move { backend.post_json("/chat", &body).await }
+    };
+    let sent_file = |question: &str| {
+        let request = mock
+            .requests()
+            .into_iter()
+            .find(|r| r.last_text().contains(question))
+            .expect("Chat request never reached Claude");
+        assert!(request.header("anthropic-beta").unwrap().contains("files-api-2025-04-14"));
+        assert!(!request.body.to_string().contains("\"base64\""));
+        let file_id = file_ids(&request.body).next();
+        file_id.expect("No file document in the request")
+    };
+
+    assert!(ask("First question").await.status().is_success());
+    assert_eq!(mock.file_uploads(), 1);
+    let first_file = sent_file("First question");
+
+    mock.expire_files();
+    assert!(ask("Second question").await.status().is_success());
+    assert_eq!(mock.file_uploads(), 2);
+    assert_ne!(sent_file("Second question"), first_file);
+}
+
+#[tokio::test]
+async fn files_that_expire_between_checks_are_uploaded_again() {
+    let mock = MockClaude::start().await;
+    mock.respond_with(metadata_responder);
+    let backend = Backend::start_with_env(
+        &mock,
+        &[("CLAUDE_FILES_API", "true"), ("CLAUDE_FILES_RECHECK_SECS", "3600")],
+    )
+    .await;
+    let document_id = backend.upload("report.pdf", TINY_PDF).await;
+    backend.wait_for_document(&document_id, has_keywords).await;
+    assert_eq!(mock.file_uploads(), 1);
+
+    // The checked upload is trusted for an hour, so only the Messages call notices
+    mock.expire_files();
+    let response = backend
+        .post_json(
+            "/chat",
+            &json!({ "document_id": document_id, "message": "Still there?" }),
+        )
+        .await;
+    assert!(response.status().is_success());
+    assert_eq!(mock.file_uploads(), 2);
+
+    mock.expire_files();
+    let events = backend
+        .post_json(
+            "/chat/stream",
+            &json!({ "document_id": document_id, "message": "And now?" }),
+        )
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(events.contains("canned reply"), "No reply in {events}");
+    assert_eq!(mock.file_uploads(), 3);
+}
+
+#[tokio::test]
+async fn failed_stream_still_records_usage() {
+    let mock = MockClaude::start().await;
+    mock.respond_with(metadata_responder);
+    let backend = Backend::start(&mock).await;
+    let document_id = backend.upload("report.pdf", TINY_PDF).await;
+    backend.wait_for_document(&document_id, has_keywords).await;
+
+    mock.push_reply(MockReply::BrokenStream("Half an ans".to_string()));
+    let events = backend
+        .post_json(
+            "/chat/stream",
+            &json!({ "document_id": document_id, "message": "Cut me off" }),
+        )
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(events.contains("event: error"), "No error event in {events}");
+
+    let conversations = backend
+        .get_json(&format!("/documents/{document_id}/conversations"))
+        .await;
+    let conversation_id = conversations[0]["id"].as_str().unwrap();
+    let usage = backend
+        .get_json(&format!("/usage/conversations/{conversation_id}"))
+        .await;
+    assert_eq!(usage["calls"], 1);
+    assert_eq!(usage["input_tokens"], 10);
+}
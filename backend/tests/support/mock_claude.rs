@@ -0,0 +1,433 @@
+//! A local stand-in for the Claude Messages API.
+//!
+//! Replies come from a FIFO script first and fall back to a responder that sees each
+//! request body, which by default answers with a canned text. Any reply can be an
+//! error, and every request is recorded so tests can check what the backend sent.
+//! Uploads to the Files API are kept until `expire_files` forgets them.
+
+use axum::{
+    body::Bytes,
+    extract::{Multipart, Path, State},
+    http::{HeaderMap, HeaderName, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Router,
+};
+use serde_json::{json, Value};
+use std::collections::{HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+pub const DEFAULT_REPLY: &str = "This is a canned reply (p. 1).";
+const MOCK_MODEL: &str = "claude-mock";
+
+/// What the mock answers a single request with
+#[derive(Debug, Clone)]
+pub enum MockReply {
+    /// A message whose only content block is this text
+    Text(String),
+    /// A complete Messages API response body
+    Message(Value),
+    /// A stream that sends this text and then fails with an `overloaded_error` event;
+    /// a request that is not streamed gets a 529 instead
+    BrokenStream(String),
+    /// An API error with this status, error type, message and extra response headers
+    Error {
+        status: u16,
+        error_type: String,
+        message: String,
+        headers: Vec<(String, String)>,
+    },
+}
+
+impl MockReply {
+    pub fn text(text: impl Into<String>) -> Self {
+        Self::Text(text.into())
+    }
+
+    pub fn error(status: u16, error_type: &str, message: &str) -> Self {
+        Self::Error {
+            status,
+            error_type: error_type.to_string(),
+            message: message.to_string(),
+            headers: Vec::new(),
+        }
+    }
+
+    /// A 429 asking the client to wait this many seconds
+    pub fn rate_limited(retry_after_secs: u64) -> Self {
+        Self::Error {
+            status: 429,
+            error_type: "rate_limit_error".to_string(),
+            message: "Injected rate limit".to_string(),
+            headers: vec![("retry-after".to_string(), retry_after_secs.to_string())],
+        }
+    }
+}
+
+/// A request the mock received
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub headers: HeaderMap,
+    pub body: Value,
+}
+
+impl RecordedRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|v| v.to_str().ok())
+    }
+
+    /// Concatenated text of the last message's text blocks
+    pub fn last_text(&self) -> String {
+        request_text(&self.body)
+    }
+}
+
+type Responder = Arc<dyn Fn(&Value) -> MockReply + Send + Sync>;
+
+struct MockState {
+    script: VecDeque<MockReply>,
+    responder: Responder,
+    latency: Duration,
+    requests: Vec<RecordedRequest>,
+    /// Files API uploads that still exist
+    files: HashSet<String>,
+    uploads: usize,
+}
+
+pub struct MockClaude {
+    base_url: String,
+    state: Arc<Mutex<MockState>>,
+    server: JoinHandle<()>,
+}
+
+impl MockClaude {
+    /// Start the mock on a free local port
+    pub async fn start() -> Self {
+        let state = Arc::new(Mutex::new(MockState {
+            script: VecDeque::new(),
+            responder: Arc::new(|_| MockReply::text(DEFAULT_REPLY)),
+            latency: Duration::ZERO,
+            requests: Vec::new(),
+            files: HashSet::new(),
+            uploads: 0,
+        }));
+
+        let app = Router::new()
+            .route("/v1/messages", post(messages_handler))
+            .route("/v1/files", post(upload_file_handler))
+            .route("/v1/files/:file_id", get(get_file_handler))
+            .with_state(state.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Failed to bind mock Claude server");
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+
+        Self {
+            base_url,
+            state,
+            server,
+        }
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// Answer unscripted requests by inspecting their body
+    pub fn respond_with(&self, responder: impl Fn(&Value) -> MockReply + Send + Sync + 'static) {
+        self.state.lock().unwrap().responder = Arc::new(responder);
+    }
+
+    /// Queue a reply for the next request that arrives
+    pub fn push_reply(&self, reply: MockReply) {
+        self.state.lock().unwrap().script.push_back(reply);
+    }
+
+    /// Fail the next `count` requests with this status
+    pub fn fail_next(&self, count: usize, status: u16, error_type: &str) {
+        for _ in 0..count {
+            self.push_reply(MockReply::error(status, error_type, "Injected by the mock"));
+        }
+    }
+
+    /// Delay every reply by this long
+    pub fn set_latency(&self, latency: Duration) {
+        self.state.lock().unwrap().latency = latency;
+    }
+
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    /// Number of files uploaded through the Files API so far
+    pub fn file_uploads(&self) -> usize {
+        self.state.lock().unwrap().uploads
+    }
+
+    /// Forget every uploaded file, as if they had expired
+    pub fn expire_files(&self) {
+        self.state.lock().unwrap().files.clear();
+    }
+
+    /// Wait until the mock has received at least `count` requests
+    pub async fn wait_for_requests(&self, count: usize) -> Vec<RecordedRequest> {
+        for _ in 0..100 {
+            let requests = self.requests();
+            if requests.len() >= count {
+                return requests;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("Mock Claude server did not receive {count} requests");
+    }
+}
+
+impl Drop for MockClaude {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+async fn messages_handler(
+    State(state): State<Arc<Mutex<MockState>>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let body: Value = match serde_json::from_slice(&body) {
+        Ok(body) => body,
+        Err(e) => return error_response(400, "invalid_request_error", &e.to_string(), Vec::new()),
+    };
+
+    let (reply, latency) = {
+        let mut state = state.lock().unwrap();
+        if let Some(file_id) = file_ids(&body).find(|id| !state.files.contains(id)) {
+            let message = format!("File not found: {file_id}");
+            return error_response(404, "not_found_error", &message, Vec::new());
+        }
+        state.requests.push(RecordedRequest {
+            headers,
+            body: body.clone(),
+        });
+        let reply = match state.script.pop_front() {
+            Some(reply) => reply,
+            None => (state.responder)(&body),
+        };
+        (reply, state.latency)
+    };
+
+    if !latency.is_zero() {
+        tokio::time::sleep(latency).await;
+    }
+
+    let streamed = body.get("stream").and_then(Value::as_bool).unwrap_or(false);
+    let message = match reply {
+        MockReply::Text(text) => text_message(&text),
+        MockReply::BrokenStream(text) if streamed => {
+            let mut events = stream_events(&text_message(&text));
+            // Cut the stream off after the text, before the block and message end
+            events.truncate(events.find("event: content_block_stop").unwrap_or(events.len()));
+            let error = json!({
+                "type": "error",
+                "error": { "type": "overloaded_error", "message": "Injected by the mock" }
+            });
+            events.push_str(&format!("event: error\ndata: {error}\n\n"));
+            return ([("content-type", "text/event-stream")], events).into_response();
+        }
+        MockReply::BrokenStream(_) => {
+            return error_response(529, "overloaded_error", "Injected by the mock", Vec::new())
+        }
+        MockReply::Message(message) => message,
+        MockReply::Error {
+            status,
+            error_type,
+            message,
+            headers,
+        } => return error_response(status, &error_type, &message, headers),
+    };
+
+    if streamed {
+        (
+            [("content-type", "text/event-stream")],
+            stream_events(&message),
+        )
+            .into_response()
+    } else {
+        axum::Json(message).into_response()
+    }
+}
+
+async fn upload_file_handler(
+    State(state): State<Arc<Mutex<MockState>>>,
+    mut multipart: Multipart,
+) -> Response {
+    let mut upload = None;
+    while let Ok(Some(field)) = multipart.next_field().await {
+        if field.name() == Some("file") {
+            let filename = field.file_name().unwrap_or("file").to_string();
+            let size = field.bytes().await.map(|b| b.len()).unwrap_or(0);
+            upload = Some((filename, size));
+        }
+    }
+    let Some((filename, size)) = upload else {
+        return error_response(400, "invalid_request_error", "Missing file part", Vec::new());
+    };
+
+    let file_id = format!("file_{}", uuid::Uuid::new_v4().simple());
+    {
+        let mut state = state.lock().unwrap();
+        state.files.insert(file_id.clone());
+        state.uploads += 1;
+    }
+    axum::Json(file_metadata(&file_id, &filename, size)).into_response()
+}
+
+async fn get_file_handler(
+    State(state): State<Arc<Mutex<MockState>>>,
+    Path(file_id): Path<String>,
+) -> Response {
+    if state.lock().unwrap().files.contains(&file_id) {
+        axum::Json(file_metadata(&file_id, "file", 0)).into_response()
+    } else {
+        let message = format!("File not found: {file_id}");
+        error_response(404, "not_found_error", &message, Vec::new())
+    }
+}
+
+fn file_metadata(file_id: &str, filename: &str, size: usize) -> Value {
+    json!({
+        "id": file_id,
+        "type": "file",
+        "filename": filename,
+        "mime_type": "application/pdf",
+        "size_bytes": size,
+        "created_at": "2025-01-01T00:00:00Z",
+        "downloadable": false
+    })
+}
+
+/// Files API ids referenced by document blocks anywhere in a Messages request
+pub fn file_ids(body: &Value) -> impl Iterator<Item = String> + '_ {
+    body["messages"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|message| message["content"].as_array())
+        .flatten()
+        .flat_map(|block| {
+            // Tool results nest their own content blocks
+            std::iter::once(block).chain(block["content"].as_array().into_iter().flatten())
+        })
+        .filter(|block| block["type"] == "document" && block["source"]["type"] == "file")
+        .filter_map(|block| block["source"]["file_id"].as_str().map(str::to_string))
+}
+
+fn text_message(text: &str) -> Value {
+    json!({
+        "id": format!("msg_{}", uuid::Uuid::new_v4().simple()),
+        "type": "message",
+        "role": "assistant",
+        "model": MOCK_MODEL,
+        "content": [{ "type": "text", "text": text }],
+        "stop_reason": "end_turn",
+        "usage": { "input_tokens": 10, "output_tokens": 5 }
+    })
+}
+
+fn error_response(
+    status: u16,
+    error_type: &str,
+    message: &str,
+    headers: Vec<(String, String)>,
+) -> Response {
+    let status = StatusCode::from_u16(status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    let body = json!({
+        "type": "error",
+        "error": { "type": error_type, "message": message }
+    });
+    let mut response = (status, axum::Json(body)).into_response();
+    for (name, value) in headers {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::try_from(name),
+            HeaderValue::try_from(value),
+        ) {
+            response.headers_mut().insert(name, value);
+        }
+    }
+    response
+}
+
+/// Render a complete message as the server-sent events of a streamed response
+fn stream_events(message: &Value) -> String {
+    let mut events = Vec::new();
+    let mut start = message.clone();
+    start["content"] = json!([]);
+    events.push(("message_start", json!({ "type": "message_start", "message": start })));
+
+    let blocks = message["content"].as_array().cloned().unwrap_or_default();
+    for (index, block) in blocks.into_iter().enumerate() {
+        match block["type"].as_str() {
+            Some("text") => {
+                events.push((
+                    "content_block_start",
+                    json!({
+                        "type": "content_block_start",
+                        "index": index,
+                        "content_block": { "type": "text", "text": "" }
+                    }),
+                ));
+                events.push((
+                    "content_block_delta",
+                    json!({
+                        "type": "content_block_delta",
+                        "index": index,
+                        "delta": { "type": "text_delta", "text": block["text"] }
+                    }),
+                ));
+            }
+            _ => events.push((
+                "content_block_start",
+                json!({ "type": "content_block_start", "index": index, "content_block": block }),
+            )),
+        }
+        events.push((
+            "content_block_stop",
+            json!({ "type": "content_block_stop", "index": index }),
+        ));
+    }
+
+    events.push((
+        "message_delta",
+        json!({
+            "type": "message_delta",
+            "delta": { "stop_reason": message["stop_reason"] },
+            "usage": { "output_tokens": message["usage"]["output_tokens"] }
+        }),
+    ));
+    events.push(("message_stop", json!({ "type": "message_stop" })));
+
+    events
+        .into_iter()
+        .map(|(name, data)| format!("event: {name}\ndata: {data}\n\n"))
+        .collect()
+}
+
+/// Text of the last message in a Messages API request
+pub fn request_text(body: &Value) -> String {
+    let Some(last) = body["messages"].as_array().and_then(|m| m.last()) else {
+        return String::new();
+    };
+    match &last["content"] {
+        Value::String(text) => text.clone(),
+        Value::Array(blocks) => blocks
+            .iter()
+            .filter_map(|block| block["text"].as_str())
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    }
+}
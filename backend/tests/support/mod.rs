@@ -0,0 +1,135 @@
+//! Helpers for end-to-end tests: a mock Claude API and the backend binary running
+//! against it in a scratch directory.
+
+pub mod mock_claude;
+
+use mock_claude::MockClaude;
+use serde_json::Value;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+/// Smallest file the upload endpoint accepts as a PDF
+pub const TINY_PDF: &[u8] = b"%PDF-1.4\n1 0 obj << /Type /Catalog >> endobj\ntrailer << /Root 1 0 R >>\n%%EOF\n";
+
+pub struct Backend {
+    pub url: String,
+    pub http: reqwest::Client,
+    child: Child,
+    dir: PathBuf,
+}
+
+impl Backend {
+    /// Run the backend against the mock with its default Claude settings
+    pub async fn start(mock: &MockClaude) -> Self {
+        Self::start_with_env(mock, &[]).await
+    }
+
+    /// Run the backend against the mock with extra environment variables
+    pub async fn start_with_env(mock: &MockClaude, env: &[(&str, &str)]) -> Self {
+        let dir = std::env::temp_dir().join(format!("pdf-reader-e2e-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).expect("Failed to create scratch directory");
+
+        let port = free_port();
+        let child = Command::new(env!("CARGO_BIN_EXE_pdf-reader-backend"))
+            .current_dir(&dir)
+            .env("ANTHROPIC_API_KEY", "test-key")
+            .env("ANTHROPIC_BASE_URL", mock.base_url())
+            .env("BACKEND_PORT", port.to_string())
+            // Keep backoff between retries short so injected failures do not slow tests down
+            .env("CLAUDE_RETRY_BASE_MS", "10")
+            .envs(env.iter().copied())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("Failed to start backend");
+
+        let backend = Self {
+            url: format!("http://127.0.0.1:{port}"),
+            http: reqwest::Client::new(),
+            child,
+            dir,
+        };
+        backend.wait_until_ready().await;
+        backend
+    }
+
+    async fn wait_until_ready(&self) {
+        for _ in 0..100 {
+            if self.http.get(self.api("/documents")).send().await.is_ok() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("Backend did not start listening on {}", self.url);
+    }
+
+    pub fn api(&self, path: &str) -> String {
+        format!("{}/api{}", self.url, path)
+    }
+
+    /// Upload a PDF and return its document id
+    pub async fn upload(&self, filename: &str, pdf: &[u8]) -> String {
+        let boundary = "e2e-boundary";
+        let mut body = format!(
+            "--{boundary}\r\nContent-Disposition: form-data; name=\"pdf\"; filename=\"{filename}\"\r\nContent-Type: application/pdf\r\n\r\n"
+        )
+        .into_bytes();
+        body.extend_from_slice(pdf);
+        body.extend_from_slice(format!("\r\n--{boundary}--\r\n").as_bytes());
+
+        let response = self
+            .http
+            .post(self.api("/upload"))
+            .header("content-type", format!("multipart/form-data; boundary={boundary}"))
+            .body(body)
+            .send()
+            .await
+            .unwrap();
+        assert!(response.status().is_success(), "Upload failed: {}", response.status());
+
+        let body: Value = response.json().await.unwrap();
+        body["document_id"].as_str().unwrap().to_string()
+    }
+
+    pub async fn post_json(&self, path: &str, body: &Value) -> reqwest::Response {
+        self.http.post(self.api(path)).json(body).send().await.unwrap()
+    }
+
+    pub async fn get_json(&self, path: &str) -> Value {
+        let response = self.http.get(self.api(path)).send().await.unwrap();
+        assert!(response.status().is_success(), "GET {path} failed: {}", response.status());
+        response.json().await.unwrap()
+    }
+
+    /// Listed document with this id, once its metadata matches `ready`
+    pub async fn wait_for_document(&self, document_id: &str, ready: impl Fn(&Value) -> bool) -> Value {
+        for _ in 0..100 {
+            let documents = self.get_json("/documents").await;
+            let document = documents
+                .as_array()
+                .and_then(|docs| docs.iter().find(|doc| doc["id"] == document_id))
+                .cloned();
+            if let Some(document) = document.filter(|doc| ready(doc)) {
+                return document;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("Document {document_id} never reached the expected state");
+    }
+}
+
+impl Drop for Backend {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn free_port() -> u16 {
+    std::net::TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .map(|addr| addr.port())
+        .expect("Failed to find a free port")
+}